@@ -1,11 +1,22 @@
 use crate::archive;
+use crate::cache;
 use crate::checksum;
-use crate::config::Config;
+use crate::checksum::{Hasher, HashingReader};
+use crate::config::{self, Archive, Config, Fetcher, Verify};
+use crate::fetchers::{ChannelReader, Fetched};
 use crate::hooks::Hooks;
+use crate::schema;
+use crate::signature;
 use crate::{bin, fetchers};
-use color_eyre::eyre::{eyre, Result, WrapErr};
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
 use color_eyre::Section;
-use std::path::Path;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// number of downloaded chunks buffered while streaming into the extractor
+const STREAM_BUFFER: usize = 32;
 
 pub struct App<'a> {
     config: &'a Config,
@@ -20,52 +31,538 @@ impl<'a> App<'a> {
         })
     }
 
-    pub fn validate(&self, filename: &Path) -> Result<()> {
-        let checksum = &self.config.checksum;
-        match checksum {
-            Some(checksum) => {
-                debug!("validating checksum for {:?}", filename);
-                checksum::validate(filename, checksum)
+    /// the checksum from the chim or, if it uses checksum_url, from the remote manifest
+    pub async fn get_checksum(&self) -> Result<Option<String>> {
+        let url = match &self.config.checksum_url {
+            Some(url) => url,
+            None => return Ok(self.config.checksum.clone()),
+        };
+        let filename = config::get_filename_from_url(&self.config.url)?;
+        debug!("fetching checksum manifest {}", url);
+
+        let tmpdir = tempfile::tempdir()?;
+        let manifest = tmpdir.path().join("checksums");
+        let pin = self.config.checksum_url_checksum.as_deref();
+        let mut fetcher = fetchers::for_url(self.config, url)?;
+        if let Some(pin) = pin {
+            fetcher = fetcher.hash_with(checksum::algorithm(pin));
+        }
+        let fetched = fetcher
+            .fetch(url, &manifest)
+            .await
+            .wrap_err_with(|| format!("error fetching {}", url))?;
+        if let Some(checksum) = pin {
+            match &fetched.checksum {
+                Some(actual) => checksum::verify(actual, checksum),
+                None => checksum::validate(&manifest, checksum),
+            }
+            .with_section(|| format!("URL: {}", url))
+            .with_suggestion(|| {
+                format!(
+                    "ensure that checksum_url_checksum is valid in chim {}",
+                    self.config.chim_path.display(),
+                )
+            })?;
+        }
+
+        let checksum = checksum::parse_manifest(&fs::read_to_string(&manifest)?, &filename)
+            .ok_or_else(|| {
+                eyre!("no checksum for {} found in {}", filename, url)
+                    .suggestion("ensure checksum_url points to the manifest for this release")
+            })?;
+        debug!("found checksum {} in manifest", checksum);
+
+        Ok(Some(checksum))
+    }
+
+    /// the detached signature from signature_url, if the chim has one
+    pub async fn get_signature(&self) -> Result<Option<Vec<u8>>> {
+        let url = match &self.config.signature_url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        if self.config.public_key.is_none() {
+            return Err(eyre!("signature_url is set but public_key is not")
+                .suggestion("set public_key in the chim to the key that signed the release"));
+        }
+        debug!("fetching signature {}", url);
+
+        let tmpdir = tempfile::tempdir()?;
+        let filename = tmpdir.path().join("signature");
+        fetchers::for_url(self.config, url)?
+            .fetch(url, &filename)
+            .await
+            .wrap_err_with(|| format!("error fetching {}", url))?;
+
+        Ok(Some(fs::read(filename)?))
+    }
+
+    pub fn validate(
+        &self,
+        filename: &Path,
+        fetched: &Fetched,
+        checksum: Option<&str>,
+        signature: Option<&[u8]>,
+    ) -> Result<()> {
+        self.validate_signature(filename, signature)?;
+        self.validate_checksum(filename, fetched, checksum)
+    }
+
+    fn validate_signature(&self, filename: &Path, signature: Option<&[u8]>) -> Result<()> {
+        match (signature, &self.config.public_key) {
+            (Some(signature), Some(public_key)) => {
+                signature::verify(filename, signature, public_key)
                     .with_section(|| format!("URL: {}", self.config.url))
                     .with_suggestion(|| {
                         format!(
-                            "ensure that checksum is valid in chim {}",
+                            "ensure that public_key is valid in chim {}",
                             self.config.chim_path.display(),
                         )
                     })?;
+                debug!("signature is valid");
+                Ok(())
+            }
+            _ if self.config.policy.require_signature => Err(eyre!(
+                "signature is required but {} has no signature_url",
+                self.config.url
+            )
+            .suggestion(
+                "set signature_url and public_key in the chim or unset CHIM_REQUIRE_SIGNATURE",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// checks the size of the artifact against chim.lock
+    fn validate_size(&self, actual: u64) -> Result<()> {
+        match self.config.size {
+            Some(size) if size != actual => Err(eyre!(
+                "size mismatch: expected {} bytes, got {}",
+                size,
+                actual
+            ))
+            .with_section(|| format!("URL: {}", self.config.url))
+            .suggestion("run `chim checksums --lock` if the artifact was republished"),
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_checksum(
+        &self,
+        filename: &Path,
+        fetched: &Fetched,
+        checksum: Option<&str>,
+    ) -> Result<()> {
+        self.validate_size(fs::metadata(filename)?.len())?;
+        match checksum {
+            Some(checksum) => {
+                debug!("validating checksum for {:?}", filename);
+                self.check_checksum_algorithm(checksum)?;
+                let result = match &fetched.checksum {
+                    Some(actual)
+                        if checksum::algorithm(actual) == checksum::algorithm(checksum) =>
+                    {
+                        checksum::verify(actual, checksum)
+                    }
+                    // external CLI fetchers only write the file
+                    _ => checksum::validate(filename, checksum),
+                };
+                self.checksum_context(result)?;
                 debug!("checksum is valid");
                 Ok(())
             }
-            None if self.config.paranoid => Err(eyre!("checksum is required in paranoid mode")),
-            None => {
-                info!("no checksum specified for {:?}", filename);
+            None => self.no_checksum(),
+        }
+    }
+
+    fn checksum_context(&self, result: Result<()>) -> Result<()> {
+        result
+            .with_section(|| format!("URL: {}", self.config.url))
+            .with_suggestion(|| {
+                format!(
+                    "ensure that checksum is valid in chim {}",
+                    self.config.chim_path.display(),
+                )
+            })
+    }
+
+    fn check_checksum_algorithm(&self, checksum: &str) -> Result<()> {
+        let algo = checksum::algorithm(checksum);
+        if self.config.policy.forbid_weak_checksums && checksum::is_weak(algo) {
+            return Err(eyre!("{algo} checksums are not allowed by the policy")
+                .suggestion("use a stronger algorithm such as sha256 or blake3"));
+        }
+
+        Ok(())
+    }
+
+    fn no_checksum(&self) -> Result<()> {
+        match self.config.policy.require_checksum {
+            true => Err(
+                eyre!("checksum is required by the policy").suggestion(format!(
+                    "run `chim checksums {}` to add one",
+                    self.config.chim_path.display()
+                )),
+            ),
+            false => {
+                info!("no checksum specified for {}", self.config.url);
                 Ok(())
             }
         }
     }
 
-    pub async fn fetch(&self, output: &Path) -> Result<()> {
-        let url = match self.hooks.pre_fetch()? {
-            url if url.is_empty() => self.config.url.clone(),
-            url => url,
-        };
+    fn get_url(&self) -> Result<String> {
+        match self.hooks.pre_fetch()? {
+            url if url.is_empty() => Ok(self.config.url.clone()),
+            url => {
+                // the hook may point somewhere the chim's url did not
+                self.config.policy.check_url(&url)?;
+                Ok(url)
+            }
+        }
+    }
+
+    /// downloads the artifact, hashing it with the algorithm of `checksum` on the way
+    pub async fn fetch(&self, output: &Path, checksum: Option<&str>) -> Result<Fetched> {
+        let url = self.get_url()?;
         debug!("fetching {}", url);
 
-        fetchers::new(self.config)
+        let mut fetcher = fetchers::new(self.config);
+        if let Some(checksum) = checksum {
+            fetcher = fetcher.hash_with(checksum::algorithm(checksum));
+        }
+        let fetched = fetcher
             .fetch(&url, output)
             .await
             .wrap_err_with(|| format!("error fetching {}", url))?;
 
-        Ok(())
+        Ok(fetched)
+    }
+
+    /// true if the artifact is a tarball that can be unpacked while it downloads
+    /// signatures are verified before extraction so signed artifacts are never streamed
+    pub fn can_stream(&self) -> bool {
+        matches!(self.config.fetcher, Fetcher::Http)
+            && self.config.signature_url.is_none()
+            && !self.config.policy.require_signature
+            && self.config.nested_archives.is_empty()
+            && matches!(
+                self.config.archive,
+                Archive::TarGz | Archive::TarXz | Archive::TarBz2 | Archive::TarZst | Archive::Tar
+            )
     }
 
-    pub fn extract(&self, filename: &Path) -> Result<()> {
+    /// downloads, hashes and unpacks a tarball in a single pass into a staging directory
+    /// which is only moved into the cache once the checksum matches
+    /// returns the path to the binary
+    pub async fn fetch_and_extract(&self) -> Result<PathBuf> {
+        let checksum = self.get_checksum().await?;
+        let hasher = match &checksum {
+            Some(checksum) => {
+                self.check_checksum_algorithm(checksum)?;
+                Some(Hasher::new(checksum)?)
+            }
+            None => {
+                self.no_checksum()?;
+                None
+            }
+        };
+        let url = self.get_url()?;
+        self.hooks.pre_extract()?;
+
+        let cache_path = &self.config.cache_path;
+        let cache_root = cache_path.parent().unwrap();
+        fs::create_dir_all(cache_root)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".staging-")
+            .tempdir_in(cache_root)?;
+        debug!("streaming {} into {:?}", url, staging.path());
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let unpack = {
+            let destination = staging.path().to_path_buf();
+            let archive = self.config.archive;
+            let include = self.config.extract.clone();
+            let strip_components = self.config.strip_components;
+            let limits = self.config.policy.limits();
+            tokio::task::spawn_blocking(move || {
+                let mut input = HashingReader::new(ChannelReader::new(rx), hasher);
+                archive::unpack_stream(
+                    &mut input,
+                    archive,
+                    &destination,
+                    &include,
+                    strip_components,
+                    limits,
+                )?;
+                Ok::<_, Report>((input.size(), input.into_hasher()))
+            })
+        };
+        let fetched = fetchers::new(self.config).stream(&url, tx).await;
+        let unpacked = unpack.await?;
+        fetched.wrap_err_with(|| format!("error fetching {}", url))?;
+        let (size, hasher) = unpacked?;
+        self.validate_size(size)?;
+        if let Some(hasher) = hasher {
+            self.checksum_context(hasher.verify())?;
+            debug!("checksum is valid");
+        }
+
+        if cache_path.exists() {
+            fs::remove_dir_all(cache_path)?;
+        }
+        fs::rename(staging.into_path(), cache_path)?;
+
+        self.resolve_bin_path(checksum, None)
+    }
+
+    /// extracts the archive into the cache and returns the path to the binary
+    pub fn extract(
+        &self,
+        filename: &Path,
+        fetched: &Fetched,
+        checksum: Option<String>,
+    ) -> Result<PathBuf> {
         let dest = &self.config.cache_path;
-        debug!("extracting archive {:?} to {:?}", filename, dest);
+        let mut archive = self.get_archive(filename, fetched)?;
         self.hooks.pre_extract()?;
-        archive::extract(filename, dest, &self.config.bin_path, &self.config.archive)?;
 
-        Ok(())
+        // unpack each nested layer into a temp file until the innermost archive remains
+        let tmpdir = tempfile::tempdir()?;
+        let mut filename = filename.to_path_buf();
+        for (i, nested) in self.config.nested_archives.iter().enumerate() {
+            let output = tmpdir.path().join(format!("layer{}", i + 1));
+            debug!(
+                "extracting {:?} layer {:?} to {:?}",
+                archive, filename, output
+            );
+            archive::extract_inner(&filename, &archive, self.config.inner.as_deref(), &output)?;
+            filename = output;
+            archive = *nested;
+        }
+
+        debug!(
+            "extracting {:?} archive {:?} to {:?}",
+            archive, filename, dest
+        );
+        let single_file = self.single_file(archive);
+        archive::extract(
+            &filename,
+            dest,
+            single_file.as_ref().unwrap_or(&self.config.bin_path),
+            &archive,
+            &self.config.extract,
+            self.config.strip_components,
+            self.config.policy.limits(),
+        )?;
+
+        self.resolve_bin_path(checksum, single_file)
+    }
+
+    /// where a sniffed single file download without a `path` is written,
+    /// None for archives which are searched for the binary instead
+    fn single_file(&self, archive: Archive) -> Option<PathBuf> {
+        match (&self.config.path, &self.config.default_path, archive) {
+            (
+                None,
+                Some(default_path),
+                Archive::Gz | Archive::Xz | Archive::Bz2 | Archive::Zst | Archive::None,
+            ) => Some(self.config.cache_path.join(default_path)),
+            _ => None,
+        }
+    }
+
+    /// resolves a glob or missing path against the extracted files, checks bin_checksum
+    /// and records the binary and a manifest of the extracted files in the cache
+    fn resolve_bin_path(
+        &self,
+        checksum: Option<String>,
+        single_file: Option<PathBuf>,
+    ) -> Result<PathBuf> {
+        let cache_path = &self.config.cache_path;
+        // other chims sharing this cache directory keep the binaries they found in it
+        let mut bin_paths = cache::Entry::load(cache_path)
+            .ok()
+            .flatten()
+            .map(|entry| entry.bin_paths)
+            .unwrap_or_default();
+        bin_paths.retain(|_, bin_path| cache_path.join(bin_path).is_file());
+        let bin_path = match &self.config.path {
+            Some(path) if !cache::is_glob(path) => self.config.bin_path.clone(),
+            path => {
+                let bin_path = match &single_file {
+                    Some(file) => file.strip_prefix(cache_path)?.to_string_lossy().to_string(),
+                    None => cache::find_bin(cache_path, path.as_deref(), &self.config.name)?,
+                };
+                let key = cache::bin_key(path.as_deref(), &self.config.name);
+                bin_paths.insert(key, bin_path.clone());
+                cache_path.join(bin_path)
+            }
+        };
+        if let Some(checksum) = &self.config.bin_checksum {
+            debug!("validating bin_checksum for {:?}", bin_path);
+            if let Err(err) = self.bin_checksum_context(checksum::validate(&bin_path, checksum)) {
+                cache::remove(cache_path)?;
+                return Err(err);
+            }
+        }
+        let mut entry = cache::Entry {
+            bin_paths,
+            checksum,
+            files: cache::manifest(cache_path)?,
+            ..cache::Entry::default()
+        };
+        entry.mark_verified();
+        entry.save(cache_path)?;
+
+        Ok(bin_path)
+    }
+
+    /// checks the installed files against the manifest recorded when they were extracted
+    /// and the checksum they were extracted from
+    /// returns false if they are stale or modified and have been removed so they can be reinstalled
+    pub fn verify_install(&self) -> Result<bool> {
+        let cache_path = &self.config.cache_path;
+        let mut entry = cache::Entry::load(cache_path)?.unwrap_or_default();
+        if let (Some(expected), Some(installed)) = (&self.config.checksum, &entry.checksum) {
+            if !expected.eq_ignore_ascii_case(installed) {
+                info!("checksum changed from {}, reinstalling", installed);
+                cache::remove(cache_path)?;
+                return Ok(false);
+            }
+        }
+        if self.config.verify == Verify::Off {
+            return Ok(true);
+        }
+        let full = match (self.config.verify, self.config.verify_interval) {
+            (Verify::Full, _) => true,
+            (_, Some(interval)) => entry.needs_full_verify(interval),
+            _ => false,
+        };
+        // a quick check only looks at the binary, a full check at every installed file
+        let bin = match full {
+            true => None,
+            false => self
+                .config
+                .bin_path
+                .strip_prefix(cache_path)
+                .ok()
+                .map(|p| p.to_string_lossy().to_string()),
+        };
+        debug!("verifying installed files (full: {})", full);
+        let result =
+            entry
+                .verify(cache_path, bin.as_deref(), full)
+                .and_then(|rehashed| match &self.config.bin_checksum {
+                    Some(checksum)
+                        if full
+                            || !entry.has_checksum(cache_path, &self.config.bin_path, checksum) =>
+                    {
+                        self.bin_checksum_context(checksum::validate(
+                            &self.config.bin_path,
+                            checksum,
+                        ))
+                        .map(|_| rehashed)
+                    }
+                    _ => Ok(rehashed),
+                });
+
+        match result {
+            Ok(rehashed) => {
+                if full {
+                    entry.mark_verified();
+                }
+                if full || rehashed {
+                    entry.save(cache_path)?;
+                }
+                Ok(true)
+            }
+            Err(err) if !self.config.policy.reinstall_on_tamper => Err(err
+                .with_section(|| format!("Cache: {}", cache_path.display()))
+                .suggestion("remove the cache directory to reinstall")),
+            Err(err) => {
+                warn!("{}, reinstalling {}", err, self.config.url);
+                cache::remove(cache_path)?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn bin_checksum_context(&self, result: Result<()>) -> Result<()> {
+        result
+            .wrap_err_with(|| {
+                format!(
+                    "invalid bin_checksum for {}",
+                    self.config.bin_path.display()
+                )
+            })
+            .with_suggestion(|| {
+                format!(
+                    "ensure that bin_checksum is valid in chim {}",
+                    self.config.chim_path.display(),
+                )
+            })
+    }
+
+    /// uses the archive from the chim if set, otherwise sniffs the downloaded file
+    fn get_archive(&self, filename: &Path, fetched: &Fetched) -> Result<Archive> {
+        if !self.config.detect_archive {
+            return Ok(self.config.archive);
+        }
+        let expected = match &fetched.filename {
+            Some(f) => config::extension_to_archive(f),
+            None => self.config.archive,
+        };
+        match archive::detect(filename)? {
+            Some(detected) => {
+                if detected != expected {
+                    warn!(
+                        "{} looks like {:?} but its extension suggests {:?}, using {:?}",
+                        self.config.url, detected, expected, detected
+                    );
+                }
+                Ok(detected)
+            }
+            None => {
+                if expected != Archive::None {
+                    warn!(
+                        "{} does not look like {:?}, extracting based on extension",
+                        self.config.url, expected
+                    );
+                }
+                Ok(expected)
+            }
+        }
+    }
+
+    /// `env` from the chim with `path_prepend` added to the front of PATH
+    fn get_env(&self) -> Result<Vec<(OsString, OsString)>> {
+        let mut env = self
+            .config
+            .env
+            .iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+            .collect::<Vec<_>>();
+        if !self.config.path_prepend.is_empty() {
+            let path = match self.config.env.get("PATH") {
+                Some(path) => OsString::from(path),
+                None => std::env::var_os("PATH").unwrap_or_default(),
+            };
+            let paths = self
+                .config
+                .path_prepend
+                .iter()
+                .cloned()
+                .chain(std::env::split_paths(&path));
+            let path = std::env::join_paths(paths).wrap_err("invalid path_prepend")?;
+            env.retain(|(k, _)| k != "PATH");
+            env.push(("PATH".into(), path));
+        }
+        if schema::is_lenient() {
+            env.push(("CHIM_LENIENT".into(), "1".into()));
+        }
+
+        Ok(env)
     }
 
     pub fn exec(&self, args: Vec<String>) -> Result<()> {
@@ -75,7 +572,16 @@ impl<'a> App<'a> {
         debug!("executing platform binary {:?}", bin);
         self.hooks.pre_execute()?;
         bin::new(bin.as_os_str())
-            .args(args.iter().skip(2).map(|s| s.as_ref()))
+            .args(
+                self.config
+                    .args
+                    .iter()
+                    .chain(args.iter().skip(2))
+                    .chain(&self.config.args_append)
+                    .map(|s| s.as_ref()),
+            )
+            .envs(self.get_env()?)
+            .cwd(self.config.cwd.as_ref())
             .execvp(execvp)
             .exec()?;
         self.hooks.post_execute()?;
@@ -83,3 +589,168 @@ impl<'a> App<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_local_checksum_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let chim_path = dir.path().join("tool");
+        fs::write(
+            &chim_path,
+            r#"
+            url = "https://example.com/tool.tar.gz"
+            checksum_url = "local:SHASUMS256.txt"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("SHASUMS256.txt"),
+            format!(
+                "{}  tool.tar.gz\n{}  other.tar.gz\n",
+                "a".repeat(64),
+                "d".repeat(64)
+            ),
+        )
+        .unwrap();
+        let config = Config::from_chim_file(&chim_path, "linux", "x86_64", None).unwrap();
+        let app = App::new(&config).unwrap();
+        assert_eq!(
+            app.get_checksum().await.unwrap().unwrap(),
+            format!("sha256:{}", "a".repeat(64))
+        );
+
+        fs::remove_file(dir.path().join("SHASUMS256.txt")).unwrap();
+        assert!(app.get_checksum().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_signature_url() {
+        let dir = tempfile::tempdir().unwrap();
+        for f in ["artifact", "artifact.minisig", "minisign.pub"] {
+            fs::copy(
+                Path::new("test/fixtures/signature").join(f),
+                dir.path().join(f),
+            )
+            .unwrap();
+        }
+        let chim_path = dir.path().join("tool");
+        fs::write(
+            &chim_path,
+            r#"
+            url = "https://example.com/artifact"
+            signature_url = "local:artifact.minisig"
+            public_key = "minisign.pub"
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_chim_file(&chim_path, "linux", "x86_64", None).unwrap();
+        let app = App::new(&config).unwrap();
+        let signature = app.get_signature().await.unwrap().unwrap();
+        app.validate(
+            &dir.path().join("artifact"),
+            &Fetched::default(),
+            None,
+            Some(&signature),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_shared_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("node.tar");
+        let mut builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+        for name in ["node", "npm"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(name.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("node-v1/bin/{name}"), name.as_bytes())
+                .unwrap();
+        }
+        builder.finish().unwrap();
+
+        let checksum = format!("sha256:{}", "a".repeat(64));
+        let config = |name: &str| {
+            let chim_path = dir.path().join(name);
+            let body = format!("url = \"https://example.com/node.tar\"\nchecksum = \"{checksum}\"");
+            fs::write(&chim_path, body).unwrap();
+            Config::from_chim_file(&chim_path, "linux", "x86_64", None).unwrap()
+        };
+        let (mut node, mut npm) = (config("node"), config("npm"));
+        assert_eq!(node.cache_path, npm.cache_path);
+        node.cache_path = dir.path().join("cache");
+        npm.cache_path = dir.path().join("cache");
+
+        let fetched = Fetched::default();
+        let node_bin = App::new(&node)
+            .unwrap()
+            .extract(&archive, &fetched, Some(checksum.clone()))
+            .unwrap();
+        let npm_bin = App::new(&npm)
+            .unwrap()
+            .extract(&archive, &fetched, Some(checksum.clone()))
+            .unwrap();
+        assert_eq!(fs::read_to_string(node_bin).unwrap(), "node");
+        assert_eq!(fs::read_to_string(npm_bin).unwrap(), "npm");
+
+        // each chim finds its own binary in the shared cache afterwards
+        let entry = cache::Entry::load(&node.cache_path).unwrap().unwrap();
+        for name in ["node", "npm"] {
+            let bin_path = &entry.bin_paths[&cache::bin_key(None, name)];
+            let bin = node.cache_path.join(bin_path);
+            assert_eq!(fs::read_to_string(bin).unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn test_extract_without_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let chim_path = dir.path().join("tool");
+        fs::write(&chim_path, r#"url = "https://example.com/download?id=123""#).unwrap();
+        let mut config = Config::from_chim_file(&chim_path, "linux", "x86_64", None).unwrap();
+        assert_eq!(config.path, None);
+        assert_eq!(config.default_path.as_deref(), Some("download"));
+        config.cache_path = dir.path().join("cache");
+
+        let archive = dir.path().join("download");
+        let encoder = flate2::write::GzEncoder::new(
+            fs::File::create(&archive).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "tool-v1/bin/tool", "tool".as_bytes())
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let app = App::new(&config).unwrap();
+        let bin = app.extract(&archive, &Fetched::default(), None).unwrap();
+        assert_eq!(bin, config.cache_path.join("tool-v1/bin/tool"));
+        assert_eq!(fs::read_to_string(bin).unwrap(), "tool");
+        // recorded so the next run finds the binary instead of downloading again
+        let entry = cache::Entry::load(&config.cache_path).unwrap().unwrap();
+        assert_eq!(
+            entry.bin_paths[&cache::bin_key(None, "tool")],
+            "tool-v1/bin/tool"
+        );
+
+        // a download that isn't an archive is the binary itself
+        fs::remove_dir_all(&config.cache_path).unwrap();
+        fs::write(&archive, "#!/bin/sh\n").unwrap();
+        let bin = app.extract(&archive, &Fetched::default(), None).unwrap();
+        assert_eq!(bin, config.cache_path.join("download"));
+        assert_eq!(fs::read_to_string(bin).unwrap(), "#!/bin/sh\n");
+        let entry = cache::Entry::load(&config.cache_path).unwrap().unwrap();
+        assert_eq!(entry.bin_paths[&cache::bin_key(None, "tool")], "download");
+    }
+}
@@ -1,72 +1,842 @@
 use crate::config;
 use bzip2::read::BzDecoder;
-use color_eyre::Result;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::{Result, Section, SectionExt};
 use flate2::read::GzDecoder;
-use std::fs::{create_dir_all, File};
+use glob::{MatchOptions, Pattern};
+use std::fs::{self, create_dir_all, File};
+use std::io;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use tar::Archive;
 use xz::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 #[cfg(not(target_os = "windows"))]
 use std::os::unix::fs::PermissionsExt;
 
+/// tar headers are 512 bytes with the "ustar" magic at offset 257
+const TAR_HEADER_LEN: usize = 512;
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// the file type bits of a unix mode and the type of a symlink
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// caps on how much an archive may unpack, None is unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_size: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+/// counts what has been unpacked so far against the limits
+struct Usage {
+    limits: Limits,
+    size: u64,
+    files: u64,
+}
+
+impl Usage {
+    fn new(limits: Limits) -> Usage {
+        Usage {
+            limits,
+            size: 0,
+            files: 0,
+        }
+    }
+
+    fn add(&mut self, size: u64) -> Result<()> {
+        self.size += size;
+        self.files += 1;
+        if let Some(max) = self.limits.max_size.filter(|max| self.size > *max) {
+            return Err(
+                eyre!("archive exceeds the maximum extracted size of {max} bytes")
+                    .suggestion("raise max_extracted_size in the policy if the archive is trusted"),
+            );
+        }
+        if let Some(max) = self.limits.max_files.filter(|max| self.files > *max) {
+            return Err(
+                eyre!("archive exceeds the maximum of {max} extracted files").suggestion(
+                    "raise max_extracted_files in the policy if the archive is trusted",
+                ),
+            );
+        }
+
+        Ok(())
+    }
+}
+
 pub fn extract(
     filename: &Path,
     destination: &Path,
     bin_path: &Path,
     archive: &config::Archive,
+    include: &[String],
+    strip_components: usize,
+    limits: Limits,
 ) -> Result<()> {
     let file = File::open(filename)?;
-    let mut input = decode(file, archive);
+    let input = decode(file, archive)?;
+    let filter = Filter::new(include, strip_components)?;
 
     match archive {
         config::Archive::TarGz
         | config::Archive::TarXz
         | config::Archive::TarBz2
-        | config::Archive::Tar => {
-            let mut archive = Archive::new(input);
-            archive.unpack(destination)?;
-        }
+        | config::Archive::TarZst
+        | config::Archive::Tar => unpack_tar(input, destination, &filter, limits)?,
         config::Archive::Gz
         | config::Archive::Xz
         | config::Archive::Bz2
+        | config::Archive::Zst
         | config::Archive::None => {
             create_dir_all(Path::new(bin_path).parent().unwrap())?;
             let mut output = File::create(bin_path)?;
             make_executable(&mut output)?;
 
-            std::io::copy(&mut input, &mut output)?;
+            // read one byte past the limit so an oversized file is detected
+            let max = limits.max_size.map_or(u64::MAX, |m| m.saturating_add(1));
+            let size = std::io::copy(&mut input.take(max), &mut output)?;
+            Usage::new(limits).add(size)?;
+        }
+        config::Archive::Zip => unpack_zip(File::open(filename)?, destination, &filter, limits)?,
+    }
+
+    Ok(())
+}
+
+/// unpacks the single file selected by `inner` out of a nested archive layer into `output`
+/// if `inner` is not set, the layer must contain exactly one file
+pub fn extract_inner(
+    filename: &Path,
+    archive: &config::Archive,
+    inner: Option<&str>,
+    output: &Path,
+) -> Result<()> {
+    let selector = match inner {
+        Some(inner) => {
+            Some(Pattern::new(inner).wrap_err_with(|| format!("invalid inner pattern: {inner}"))?)
+        }
+        None => None,
+    };
+    let is_selected = |path: &Path| match &selector {
+        Some(selector) => {
+            selector.matches_path_with(path, MATCH_OPTIONS)
+                || path
+                    .file_name()
+                    .is_some_and(|f| selector.matches_path_with(Path::new(f), MATCH_OPTIONS))
+        }
+        None => true,
+    };
+    let mut input = decode(File::open(filename)?, archive)?;
+
+    let found = match archive {
+        config::Archive::TarGz
+        | config::Archive::TarXz
+        | config::Archive::TarBz2
+        | config::Archive::TarZst
+        | config::Archive::Tar => {
+            let mut found = vec![];
+            for entry in Archive::new(input).entries()? {
+                let mut entry = entry?;
+                let path = entry.path()?.into_owned();
+                if !entry.header().entry_type().is_file() || !is_selected(&path) {
+                    continue;
+                }
+                if found.is_empty() {
+                    std::io::copy(&mut entry, &mut File::create(output)?)?;
+                }
+                found.push(path.to_string_lossy().to_string());
+            }
+            found
         }
         config::Archive::Zip => {
-            let input = File::open(filename)?;
-            let mut archive = zip::ZipArchive::new(input)?;
-            archive.extract(destination)?;
+            let mut archive = zip::ZipArchive::new(File::open(filename)?)?;
+            let found = archive
+                .file_names()
+                .filter(|f| !f.ends_with('/') && is_selected(Path::new(f)))
+                .map(String::from)
+                .collect::<Vec<_>>();
+            if let [name] = found.as_slice() {
+                std::io::copy(&mut archive.by_name(name)?, &mut File::create(output)?)?;
+            }
+            found
+        }
+        config::Archive::Gz
+        | config::Archive::Xz
+        | config::Archive::Bz2
+        | config::Archive::Zst
+        | config::Archive::None => {
+            std::io::copy(&mut input, &mut File::create(output)?)?;
+            return Ok(());
+        }
+    };
+
+    let what = match inner {
+        Some(inner) => format!("file matching {inner}"),
+        None => "file".to_string(),
+    };
+    match found.as_slice() {
+        [f] => {
+            debug!("unpacked nested archive {}", f);
+            Ok(())
+        }
+        [] => Err(eyre!("no {what} found in nested archive")
+            .suggestion("set inner in the chim to the nested archive's filename")),
+        _ => Err(eyre!("multiple files found in nested archive")
+            .section(found.join("\n").header("Candidates:"))
+            .suggestion("set inner in the chim to one of the candidates")),
+    }
+}
+
+/// selects which archive entries get unpacked and where they are written
+struct Filter {
+    include: Vec<Pattern>,
+    strip_components: usize,
+}
+
+impl Filter {
+    fn new(include: &[String], strip_components: usize) -> Result<Filter> {
+        let include = include
+            .iter()
+            .map(|p| Pattern::new(p).wrap_err_with(|| format!("invalid extract pattern: {p}")))
+            .collect::<Result<_>>()?;
+
+        Ok(Filter {
+            include,
+            strip_components,
+        })
+    }
+
+    /// returns the path of an entry relative to the destination
+    /// or None if the entry should be skipped
+    fn apply(&self, path: &Path) -> Option<PathBuf> {
+        let mut components = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(c) => components.push(c),
+                Component::CurDir => {}
+                // never write outside of the destination
+                _ => return None,
+            }
+        }
+        let path: PathBuf = components.into_iter().skip(self.strip_components).collect();
+        if path.as_os_str().is_empty() || !self.is_included(&path) {
+            return None;
+        }
+
+        Some(path)
+    }
+
+    /// an entry is included if a pattern matches it or any of its parent directories
+    fn is_included(&self, path: &Path) -> bool {
+        self.include.is_empty()
+            || path.ancestors().any(|p| {
+                self.include
+                    .iter()
+                    .any(|i| i.matches_path_with(p, MATCH_OPTIONS))
+            })
+    }
+}
+
+fn unpack_tar<R: Read>(
+    input: R,
+    destination: &Path,
+    filter: &Filter,
+    limits: Limits,
+) -> Result<()> {
+    let mut archive = Archive::new(input);
+    let sandbox = Sandbox::new(destination)?;
+    let mut usage = Usage::new(limits);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = match filter.apply(&entry.path()?) {
+            Some(path) => path,
+            None => continue,
+        };
+        usage.add(entry.header().size()?)?;
+        trace!("extracting {:?}", path);
+        let entry_type = entry.header().entry_type();
+        let link_name = entry.link_name()?.map(|l| l.into_owned());
+        match link_name {
+            _ if entry_type.is_dir() => {
+                sandbox.create_dir(&path)?;
+            }
+            Some(target) if entry_type.is_symlink() => sandbox.symlink(&path, &target)?,
+            // hardlink targets are archive paths so they are filtered like entries
+            Some(target) if entry_type.is_hard_link() => match filter.apply(&target) {
+                Some(target) => sandbox.hard_link(&path, &target)?,
+                None => {
+                    return Err(eyre!(
+                        "hardlink {} points to a file that isn't extracted",
+                        path.display()
+                    ))
+                }
+            },
+            _ if entry_type.is_file() => {
+                entry.unpack(sandbox.prepare(&path)?)?;
+            }
+            _ => debug!("skipping {:?} entry {:?}", entry_type, path),
         }
     }
 
     Ok(())
 }
 
-fn decode(file: File, archive: &config::Archive) -> Box<dyn Read> {
-    match archive {
-        config::Archive::TarGz | config::Archive::Gz => Box::new(GzDecoder::new(file)),
-        config::Archive::TarXz | config::Archive::Xz => Box::new(XzDecoder::new(file)),
-        config::Archive::TarBz2 | config::Archive::Bz2 => Box::new(BzDecoder::new(file)),
-        config::Archive::Tar | config::Archive::None | config::Archive::Zip => Box::new(file),
+fn unpack_zip(input: File, destination: &Path, filter: &Filter, limits: Limits) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(input)?;
+    let sandbox = Sandbox::new(destination)?;
+    let mut usage = Usage::new(limits);
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = match file.enclosed_name().and_then(|p| filter.apply(p)) {
+            Some(path) => path,
+            None => continue,
+        };
+        usage.add(file.size())?;
+        trace!("extracting {:?}", path);
+        if file.is_dir() {
+            sandbox.create_dir(&path)?;
+            continue;
+        }
+        // zip stores a symlink as a file holding its target with the S_IFLNK mode
+        if file
+            .unix_mode()
+            .is_some_and(|mode| mode & S_IFMT == S_IFLNK)
+        {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            sandbox.symlink(&path, Path::new(&target))?;
+            continue;
+        }
+        let mut output = File::create(sandbox.prepare(&path)?)?;
+        std::io::copy(&mut file, &mut output)?;
+        if let Some(mode) = file.unix_mode() {
+            set_mode(&mut output, mode)?;
+        }
     }
+
+    Ok(())
+}
+
+/// writes archive entries under a directory without letting any of them escape it
+/// through `..`, absolute paths, symlinks or hardlinks
+struct Sandbox {
+    /// canonical so symlinks can be resolved and compared against it
+    root: PathBuf,
+}
+
+impl Sandbox {
+    fn new(destination: &Path) -> Result<Sandbox> {
+        create_dir_all(destination)?;
+
+        Ok(Sandbox {
+            root: fs::canonicalize(destination)?,
+        })
+    }
+
+    /// creates `dir` one component at a time so a symlink never leads the way out
+    fn create_dir(&self, dir: &Path) -> Result<PathBuf> {
+        let mut current = self.root.clone();
+        for component in dir.components() {
+            current.push(component);
+            match fs::symlink_metadata(&current) {
+                Ok(_) => current = self.resolve(&current)?,
+                Err(_) => fs::create_dir(&current)?,
+            }
+        }
+
+        Ok(current)
+    }
+
+    /// creates the parents of `path` and returns where to write it
+    /// an existing file or symlink there is removed so it isn't written through
+    fn prepare(&self, path: &Path) -> Result<PathBuf> {
+        let parent = self.create_dir(path.parent().unwrap_or(Path::new("")))?;
+        let path = parent.join(path.file_name().unwrap());
+        if fs::symlink_metadata(&path).is_ok_and(|m| !m.is_dir()) {
+            fs::remove_file(&path)?;
+        }
+
+        Ok(path)
+    }
+
+    /// only relative targets that resolve inside the sandbox are allowed
+    fn symlink(&self, path: &Path, target: &Path) -> Result<()> {
+        let link = self.prepare(path)?;
+        let parent = link.parent().unwrap().strip_prefix(&self.root)?;
+        let mut depth = parent.components().count();
+        for component in target.components() {
+            match component {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+                Component::ParentDir if depth > 0 => depth -= 1,
+                _ => {
+                    return Err(eyre!(
+                        "symlink {} -> {} points outside of the extracted archive",
+                        path.display(),
+                        target.display()
+                    ))
+                }
+            }
+        }
+
+        symlink(target, &link)
+    }
+
+    fn hard_link(&self, path: &Path, target: &Path) -> Result<()> {
+        let target = self.resolve(&self.root.join(target))?;
+        fs::hard_link(target, self.prepare(path)?)?;
+
+        Ok(())
+    }
+
+    /// the canonical form of `path` which must be inside the sandbox
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        match fs::canonicalize(path) {
+            Ok(resolved) if resolved.starts_with(&self.root) => Ok(resolved),
+            _ => Err(eyre!(
+                "{} resolves outside of the extracted archive",
+                path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, link)?;
+    Ok(())
 }
 
 #[cfg(target_os = "windows")]
-fn make_executable(_file: &mut File) -> Result<()> {
+fn symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_file(target, link)?;
     Ok(())
 }
 
-#[cfg(not(target_os = "windows"))]
+/// unpacks a tarball while it is still being downloaded
+/// the compression is sniffed from the first bytes with `archive` as the fallback
+/// the input is always read to the end so all of it gets hashed
+pub fn unpack_stream<R: Read>(
+    mut input: R,
+    archive: config::Archive,
+    destination: &Path,
+    include: &[String],
+    strip_components: usize,
+    limits: Limits,
+) -> Result<()> {
+    let filter = Filter::new(include, strip_components)?;
+    let header = read_header(&mut input)?;
+    let detected = match sniff(&header) {
+        Some(config::Archive::Gz) => config::Archive::TarGz,
+        Some(config::Archive::Xz) => config::Archive::TarXz,
+        Some(config::Archive::Bz2) => config::Archive::TarBz2,
+        Some(config::Archive::Zst) => config::Archive::TarZst,
+        Some(config::Archive::Tar) => config::Archive::Tar,
+        Some(config::Archive::Zip) => {
+            return Err(eyre!("zip archives cannot be streamed")
+                .suggestion("set archive = \"zip\" in the chim"))
+        }
+        _ => archive,
+    };
+    if detected != archive {
+        warn!(
+            "download looks like {:?} but its extension suggests {:?}, using {:?}",
+            detected, archive, detected
+        );
+    }
+
+    let mut input = io::Cursor::new(header).chain(input);
+    unpack_tar(decode(&mut input, &detected)?, destination, &filter, limits)?;
+    io::copy(&mut input, &mut io::sink())?;
+
+    Ok(())
+}
+
+/// detects the archive format of a file by its magic bytes
+/// returns None if the file does not look like any supported archive
+pub fn detect(filename: &Path) -> Result<Option<config::Archive>> {
+    let header = read_header(File::open(filename)?)?;
+    let archive = match sniff(&header) {
+        Some(compression @ config::Archive::Gz)
+        | Some(compression @ config::Archive::Xz)
+        | Some(compression @ config::Archive::Bz2)
+        | Some(compression @ config::Archive::Zst) => {
+            // peek inside the compressed stream to see if it wraps a tarball
+            let inner = decode(File::open(filename)?, &compression)
+                .and_then(read_header)
+                .unwrap_or_default();
+            match (compression, is_tar(&inner)) {
+                (config::Archive::Gz, true) => Some(config::Archive::TarGz),
+                (config::Archive::Xz, true) => Some(config::Archive::TarXz),
+                (config::Archive::Bz2, true) => Some(config::Archive::TarBz2),
+                (config::Archive::Zst, true) => Some(config::Archive::TarZst),
+                (compression, _) => Some(compression),
+            }
+        }
+        archive => archive,
+    };
+
+    Ok(archive)
+}
+
+fn sniff(header: &[u8]) -> Option<config::Archive> {
+    match header {
+        [0x1f, 0x8b, ..] => Some(config::Archive::Gz),
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(config::Archive::Xz),
+        [b'B', b'Z', b'h', ..] => Some(config::Archive::Bz2),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(config::Archive::Zst),
+        [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => Some(config::Archive::Zip),
+        header if is_tar(header) => Some(config::Archive::Tar),
+        _ => None,
+    }
+}
+
+fn is_tar(header: &[u8]) -> bool {
+    header.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 5) == Some(b"ustar")
+}
+
+fn read_header<R: Read>(input: R) -> Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(TAR_HEADER_LEN);
+    input.take(TAR_HEADER_LEN as u64).read_to_end(&mut header)?;
+
+    Ok(header)
+}
+
+fn decode<'a, R: Read + 'a>(input: R, archive: &config::Archive) -> Result<Box<dyn Read + 'a>> {
+    Ok(match archive {
+        config::Archive::TarGz | config::Archive::Gz => Box::new(GzDecoder::new(input)),
+        config::Archive::TarXz | config::Archive::Xz => Box::new(XzDecoder::new(input)),
+        config::Archive::TarBz2 | config::Archive::Bz2 => Box::new(BzDecoder::new(input)),
+        config::Archive::TarZst | config::Archive::Zst => Box::new(ZstdDecoder::new(input)?),
+        config::Archive::Tar | config::Archive::None | config::Archive::Zip => Box::new(input),
+    })
+}
+
 fn make_executable(file: &mut File) -> Result<()> {
+    set_mode(file, 0o755)
+}
+
+#[cfg(target_os = "windows")]
+fn set_mode(_file: &mut File, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn set_mode(file: &mut File, mode: u32) -> Result<()> {
     let metadata = file.metadata()?;
     let mut permissions = metadata.permissions();
-    permissions.set_mode(0o755);
+    permissions.set_mode(mode);
     file.set_permissions(permissions)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    const ENTRIES: [&str; 3] = [
+        "tool-1.0/README",
+        "tool-1.0/bin/tool",
+        "tool-1.0/lib/a/b.so",
+    ];
+
+    fn tarball() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ENTRIES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(5);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, &b"hello"[..])
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zipfile() -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for name in ENTRIES {
+            writer
+                .start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(b"hello").unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn extract_bytes(
+        data: &[u8],
+        archive: config::Archive,
+        include: &[&str],
+        strip_components: usize,
+    ) -> Vec<String> {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("download");
+        let dest = dir.path().join("dest");
+        std::fs::write(&file, data).unwrap();
+        let include = include.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        extract(
+            &file,
+            &dest,
+            &dest,
+            &archive,
+            &include,
+            strip_components,
+            Limits::default(),
+        )
+        .unwrap();
+
+        let mut files = ENTRIES
+            .iter()
+            .flat_map(|e| [e.to_string(), e.splitn(2, '/').last().unwrap().to_string()])
+            .filter(|f| dest.join(f).is_file())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_extract_tar() {
+        let tar = tarball();
+        assert_eq!(extract_bytes(&tar, config::Archive::Tar, &[], 0), ENTRIES);
+        assert_eq!(
+            extract_bytes(&tar, config::Archive::Tar, &[], 1),
+            ["README", "bin/tool", "lib/a/b.so"]
+        );
+        assert_eq!(
+            extract_bytes(&tar, config::Archive::Tar, &["bin/tool", "lib/**"], 1),
+            ["bin/tool", "lib/a/b.so"]
+        );
+        assert_eq!(
+            extract_bytes(&tar, config::Archive::Tar, &["*/bin"], 0),
+            ["tool-1.0/bin/tool"]
+        );
+    }
+
+    #[test]
+    fn test_usage() {
+        let mut usage = Usage::new(Limits {
+            max_size: Some(10),
+            max_files: Some(2),
+        });
+        usage.add(5).unwrap();
+        usage.add(5).unwrap();
+        assert!(usage.add(0).is_err());
+
+        let mut usage = Usage::new(Limits {
+            max_size: Some(10),
+            max_files: None,
+        });
+        usage.add(10).unwrap();
+        assert!(usage.add(1).is_err());
+        Usage::new(Limits::default()).add(u64::MAX).unwrap();
+    }
+
+    #[test]
+    fn test_extract_limits() {
+        let dir = tempdir().unwrap();
+        for (archive, data) in [
+            (config::Archive::Tar, tarball()),
+            (config::Archive::Zip, zipfile()),
+        ] {
+            let file = dir.path().join("download");
+            std::fs::write(&file, data).unwrap();
+            let extract_with = |max_size, max_files| {
+                let limits = Limits {
+                    max_size,
+                    max_files,
+                };
+                let dest = tempdir().unwrap();
+                extract(&file, dest.path(), dest.path(), &archive, &[], 0, limits)
+            };
+            extract_with(Some(15), Some(3)).unwrap();
+            assert!(extract_with(Some(14), None).is_err());
+            assert!(extract_with(None, Some(2)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_unpack_stream() {
+        let dir = tempdir().unwrap();
+        let data = gzip(&tarball());
+        let mut input = std::io::Cursor::new(&data);
+        unpack_stream(
+            &mut input,
+            config::Archive::TarXz,
+            dir.path(),
+            &[],
+            1,
+            Limits::default(),
+        )
+        .unwrap();
+
+        assert!(dir.path().join("bin/tool").is_file());
+        assert_eq!(input.position(), data.len() as u64);
+    }
+
+    #[test]
+    fn test_extract_inner() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("download");
+        let output = dir.path().join("inner");
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for name in ["tool-linux.tar.gz", "tool-macos.tar.gz"] {
+            writer
+                .start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(&gzip(&tarball())).unwrap();
+        }
+        std::fs::write(&file, writer.finish().unwrap().into_inner()).unwrap();
+
+        let zip = config::Archive::Zip;
+        extract_inner(&file, &zip, Some("tool-linux.tar.gz"), &output).unwrap();
+        assert_eq!(detect(&output).unwrap(), Some(config::Archive::TarGz));
+        assert!(extract_inner(&file, &zip, Some("*.tar.gz"), &output).is_err());
+        assert!(extract_inner(&file, &zip, None, &output).is_err());
+        assert!(extract_inner(&file, &zip, Some("*.zip"), &output).is_err());
+    }
+
+    fn hostile_tarball(entries: &[(&str, tar::EntryType, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, entry_type, link) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o755);
+            header.set_size(if entry_type.is_file() { 5 } else { 0 });
+            if !link.is_empty() {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            let data: &[u8] = if entry_type.is_file() { b"hello" } else { b"" };
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn extract_to(data: &[u8], archive: config::Archive, dest: &Path) -> Result<()> {
+        let file = dest.with_extension("download");
+        std::fs::write(&file, data).unwrap();
+        extract(&file, dest, dest, &archive, &[], 0, Limits::default())
+    }
+
+    #[test]
+    fn test_extract_tar_symlinks() {
+        let dir = tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        let outside = outside.to_str().unwrap();
+        let dest = dir.path().join("dest");
+
+        // a symlinked directory must not let a later entry write outside
+        let tar = hostile_tarball(&[
+            ("lib", tar::EntryType::Symlink, outside),
+            ("lib/.bashrc", tar::EntryType::Regular, ""),
+        ]);
+        assert!(extract_to(&tar, config::Archive::Tar, &dest).is_err());
+        let tar = hostile_tarball(&[
+            ("a/lib", tar::EntryType::Symlink, "../../outside"),
+            ("a/lib/.bashrc", tar::EntryType::Regular, ""),
+        ]);
+        assert!(extract_to(&tar, config::Archive::Tar, &dest).is_err());
+        let tar = hostile_tarball(&[("passwd", tar::EntryType::Link, "/etc/passwd")]);
+        assert!(extract_to(&tar, config::Archive::Tar, &dest).is_err());
+        assert!(!dir.path().join("outside/.bashrc").exists());
+
+        let tar = hostile_tarball(&[
+            ("tool-1.0/bin/tool", tar::EntryType::Regular, ""),
+            ("tool-1.0/bin/alias", tar::EntryType::Symlink, "tool"),
+            ("tool-1.0/current", tar::EntryType::Symlink, "."),
+            (
+                "tool-1.0/lib/tool",
+                tar::EntryType::Link,
+                "tool-1.0/bin/tool",
+            ),
+        ]);
+        let dest = dir.path().join("contained");
+        extract_to(&tar, config::Archive::Tar, &dest).unwrap();
+        assert_eq!(
+            std::fs::read_link(dest.join("tool-1.0/bin/alias")).unwrap(),
+            Path::new("tool")
+        );
+        assert_eq!(
+            std::fs::read(dest.join("tool-1.0/current/lib/tool")).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_extract_zip_symlinks() {
+        let zip_with_link = |target: &str| {
+            let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            let options = zip::write::FileOptions::default();
+            writer.start_file("bin/tool", options).unwrap();
+            writer.write_all(b"hello").unwrap();
+            writer.add_symlink("bin/alias", target, options).unwrap();
+            writer.finish().unwrap().into_inner()
+        };
+        let dir = tempdir().unwrap();
+
+        let dest = dir.path().join("contained");
+        extract_to(&zip_with_link("tool"), config::Archive::Zip, &dest).unwrap();
+        assert_eq!(std::fs::read(dest.join("bin/alias")).unwrap(), b"hello");
+
+        let dest = dir.path().join("escaping");
+        let zip = zip_with_link("../../outside");
+        assert!(extract_to(&zip, config::Archive::Zip, &dest).is_err());
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let zip = zipfile();
+        assert_eq!(extract_bytes(&zip, config::Archive::Zip, &[], 0), ENTRIES);
+        assert_eq!(
+            extract_bytes(&zip, config::Archive::Zip, &["bin/*"], 1),
+            ["bin/tool"]
+        );
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn detect_bytes(data: &[u8]) -> Option<config::Archive> {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("download");
+        std::fs::write(&file, data).unwrap();
+        detect(&file).unwrap()
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect_bytes(&tarball()), Some(config::Archive::Tar));
+        assert_eq!(
+            detect_bytes(&gzip(&tarball())),
+            Some(config::Archive::TarGz)
+        );
+        assert_eq!(detect_bytes(&gzip(b"#!/bin/sh")), Some(config::Archive::Gz));
+        assert_eq!(
+            detect_bytes(&zstd::encode_all(&tarball()[..], 0).unwrap()),
+            Some(config::Archive::TarZst)
+        );
+        assert_eq!(detect_bytes(b"PK\x03\x04rest"), Some(config::Archive::Zip));
+        assert_eq!(detect_bytes(b"\x7fELF"), None);
+        assert_eq!(detect_bytes(b""), None);
+    }
+}
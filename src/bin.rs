@@ -2,7 +2,8 @@
 use color_eyre::eyre::eyre;
 use color_eyre::eyre::{Context, Result};
 use itertools::Itertools;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
 use std::process::{exit, Command};
 
 pub struct Bin<T>
@@ -11,6 +12,8 @@ where
 {
     program: T,
     args: Vec<T>,
+    envs: Vec<(OsString, OsString)>,
+    cwd: Option<PathBuf>,
     execvp: bool,
 }
 
@@ -21,6 +24,8 @@ where
     Bin {
         program,
         args: Vec::new(),
+        envs: Vec::new(),
+        cwd: None,
         execvp: false,
     }
 }
@@ -38,6 +43,30 @@ where
         self
     }
 
+    pub fn envs<I, K, V>(mut self, envs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.envs = envs
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_owned(), v.as_ref().to_owned()))
+            .collect();
+
+        self
+    }
+
+    /// runs the program in `cwd`, the invoking directory is always in CHIM_ORIGINAL_CWD
+    pub fn cwd<P>(mut self, cwd: Option<P>) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.cwd = cwd.map(|cwd| cwd.as_ref().to_path_buf());
+
+        self
+    }
+
     pub fn execvp(mut self, execvp: bool) -> Self {
         self.execvp = execvp;
 
@@ -66,7 +95,15 @@ where
             display_os_str(&self.program),
             display_args(&self.args)
         );
-        let err = exec::Command::new(&self.program).args(&self.args).exec();
+        use std::os::unix::process::CommandExt;
+
+        // the environment and directory are passed to execvp rather than set on this process,
+        // other threads of the runtime may still be reading them
+        let mut cmd = Command::new(self.program()?);
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        let err = cmd.args(&self.args).envs(self.get_envs()?).exec();
 
         // always errors if it gets here
         Err(eyre!("execvp failed: {}", err))
@@ -84,7 +121,11 @@ where
             display_args(&self.args)
         );
 
-        let status = Command::new(&self.program).args(&self.args).status()?;
+        let mut cmd = Command::new(self.program()?);
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        let status = cmd.args(&self.args).envs(self.get_envs()?).status()?;
 
         debug!("subprocess exited with {status}");
 
@@ -94,6 +135,22 @@ where
 
         Ok(())
     }
+
+    /// relative paths like `bin/tool` are made absolute so they survive changing `cwd`
+    fn program(&self) -> Result<PathBuf> {
+        let program = Path::new(&self.program);
+        match self.cwd.is_some() && program.is_relative() && program.components().count() > 1 {
+            true => Ok(std::path::absolute(program)?),
+            false => Ok(program.to_path_buf()),
+        }
+    }
+
+    fn get_envs(&self) -> Result<Vec<(OsString, OsString)>> {
+        let mut envs = self.envs.clone();
+        envs.push(("CHIM_ORIGINAL_CWD".into(), std::env::current_dir()?.into()));
+
+        Ok(envs)
+    }
 }
 
 fn display_args<I>(args: &[I]) -> String
@@ -107,7 +164,7 @@ fn display_os_str<T>(os_str: T) -> String
 where
     T: AsRef<OsStr>,
 {
-    return os_str.as_ref().to_string_lossy().to_string();
+    os_str.as_ref().to_string_lossy().to_string()
 }
 
 #[cfg(test)]
@@ -120,6 +177,32 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_exec_envs() {
+        let result = new("sh")
+            .args(["-c", "test \"$CHIM_TEST_ENV\" = 1"])
+            .envs([("CHIM_TEST_ENV", "1")])
+            .exec();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_exec_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let script = format!(
+            "test \"$PWD\" = {:?} && test \"$CHIM_ORIGINAL_CWD\" = {:?}",
+            dir.path().canonicalize().unwrap(),
+            cwd
+        );
+        let result = new("sh").args(["-c", &script]).cwd(Some(dir.path())).exec();
+        assert!(result.is_ok());
+
+        let script = format!("test \"$CHIM_ORIGINAL_CWD\" = {:?}", cwd);
+        let result = new("sh").args(["-c", &script]).cwd(None::<&Path>).exec();
+        assert!(result.is_ok());
+    }
+
     #[test]
     fn test_exec_invalid_bin() {
         let result = new("invalid_bin").exec();
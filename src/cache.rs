@@ -0,0 +1,429 @@
+use crate::checksum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use color_eyre::{Section, SectionExt};
+use glob::{glob_with, MatchOptions, Pattern};
+use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// metadata about an installed cache entry
+/// stored beside the extracted directory as `<cache_path>.toml`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Entry {
+    /// paths to the binaries found by a glob or by name relative to the cache directory
+    /// keyed by `bin_key` since chims with different paths or names can share a cache directory
+    #[serde(default)]
+    pub bin_paths: BTreeMap<String, String>,
+    /// checksum of the artifact this entry was extracted from
+    pub checksum: Option<String>,
+    /// unix time of the last time every file in `files` was rehashed
+    pub verified_at: Option<u64>,
+    /// the extracted files relative to the cache directory, recorded at install time
+    #[serde(default)]
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub checksum: String,
+    pub size: u64,
+    /// modification time in nanoseconds since the unix epoch
+    pub mtime: u64,
+}
+
+impl Entry {
+    pub fn load(cache_path: &Path) -> Result<Option<Entry>> {
+        let filename = entry_path(cache_path);
+        if !filename.exists() {
+            return Ok(None);
+        }
+        trace!("reading {:?}", filename);
+        let body = fs::read_to_string(&filename)?;
+        let entry = toml::from_str(&body)
+            .wrap_err_with(|| format!("error parsing {}", filename.display()))?;
+
+        Ok(Some(entry))
+    }
+
+    pub fn save(&self, cache_path: &Path) -> Result<()> {
+        let filename = entry_path(cache_path);
+        trace!("writing {:?}", filename);
+        fs::write(filename, toml::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+impl Entry {
+    /// true if the last full verification is at least `interval` seconds old
+    pub fn needs_full_verify(&self, interval: u64) -> bool {
+        match self.verified_at {
+            Some(verified_at) => now().saturating_sub(verified_at) >= interval,
+            None => true,
+        }
+    }
+
+    pub fn mark_verified(&mut self) {
+        self.verified_at = Some(now());
+    }
+
+    /// true if the manifest recorded exactly `checksum` for `filename`
+    pub fn has_checksum(&self, cache_path: &Path, filename: &Path, checksum: &str) -> bool {
+        filename
+            .strip_prefix(cache_path)
+            .ok()
+            .and_then(|f| self.files.get(&*f.to_string_lossy()))
+            .is_some_and(|f| f.checksum == checksum)
+    }
+
+    /// checks the installed files against the recorded manifest, only `file` if it is set
+    /// files whose size or mtime changed are rehashed, every file is rehashed if `full`
+    /// a rehashed file that still matches has its new mtime recorded, returns true if any did
+    pub fn verify(&mut self, cache_path: &Path, file: Option<&str>, full: bool) -> Result<bool> {
+        let mut modified = vec![];
+        let mut rehashed = false;
+        for (name, expected) in &mut self.files {
+            if file.is_some_and(|f| f != name) {
+                continue;
+            }
+            let filename = cache_path.join(name);
+            let (size, mtime) = match stat(&filename) {
+                Ok(stat) => stat,
+                Err(_) => {
+                    modified.push(format!("{name} (missing)"));
+                    continue;
+                }
+            };
+            if size != expected.size {
+                modified.push(format!("{name} (size changed)"));
+            } else if full || mtime != expected.mtime {
+                match checksum::validate(&filename, &expected.checksum) {
+                    Ok(()) if mtime != expected.mtime => {
+                        expected.mtime = mtime;
+                        rehashed = true;
+                    }
+                    Ok(()) => {}
+                    Err(_) => modified.push(format!("{name} (checksum changed)")),
+                }
+            }
+        }
+
+        match modified.is_empty() {
+            true => Ok(rehashed),
+            false => Err(
+                eyre!("installed files were modified since they were extracted")
+                    .section(modified.join("\n").header("Modified:")),
+            ),
+        }
+    }
+}
+
+/// identifies how a chim finds its binary in `Entry::bin_paths`
+/// by the `path` glob if it has one, otherwise by the chim name
+pub fn bin_key(pattern: Option<&str>, name: &str) -> String {
+    match pattern {
+        Some(pattern) => format!("path:{pattern}"),
+        None => format!("name:{name}"),
+    }
+}
+
+fn entry_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_extension("toml")
+}
+
+/// removes an installed cache entry so it is extracted again
+pub fn remove(cache_path: &Path) -> Result<()> {
+    if cache_path.is_dir() {
+        fs::remove_dir_all(cache_path)?;
+    }
+    let filename = entry_path(cache_path);
+    if filename.exists() {
+        fs::remove_file(filename)?;
+    }
+
+    Ok(())
+}
+
+/// hashes every file under the cache directory
+pub fn manifest(cache_path: &Path) -> Result<BTreeMap<String, FileEntry>> {
+    let mut files = BTreeMap::new();
+    for filename in find_files(cache_path)? {
+        let (size, mtime) = stat(&filename)?;
+        let name = filename
+            .strip_prefix(cache_path)?
+            .to_string_lossy()
+            .to_string();
+        let entry = FileEntry {
+            checksum: format!("sha256:{}", checksum::get_checksum(&filename, "sha256")?),
+            size,
+            mtime,
+        };
+        files.insert(name, entry);
+    }
+
+    Ok(files)
+}
+
+/// symlinked directories aren't followed so their files aren't hashed twice
+fn find_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            found.extend(find_files(&path)?);
+        } else if path.is_file() {
+            found.push(path);
+        }
+    }
+
+    Ok(found)
+}
+
+fn stat(filename: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(filename)?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+
+    Ok((metadata.len(), mtime))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// finds the binary inside an extracted archive
+/// either by a glob pattern or by searching for an executable called `name`
+/// returns the path relative to the cache directory
+pub fn find_bin(cache_path: &Path, pattern: Option<&str>, name: &str) -> Result<String> {
+    let candidates = match pattern {
+        Some(pattern) => find_glob(cache_path, pattern)?,
+        None => find_executables(cache_path, name)?,
+    };
+    let candidates = candidates
+        .iter()
+        .filter_map(|p| p.strip_prefix(cache_path).ok())
+        .map(|p| p.to_string_lossy().to_string())
+        .sorted()
+        .collect::<Vec<_>>();
+    let what = match pattern {
+        Some(pattern) => format!("files matching {pattern}"),
+        None => format!("executables named {name}"),
+    };
+
+    match candidates.as_slice() {
+        [bin] => {
+            debug!("found {} at {}", what, bin);
+            Ok(bin.clone())
+        }
+        [] => Err(eyre!("no {what} found in archive")
+            .suggestion("set path in the chim to the binary inside the archive")),
+        _ => Err(eyre!("multiple {what} found in archive")
+            .section(candidates.join("\n").header("Candidates:"))
+            .suggestion("set path in the chim to one of the candidates")),
+    }
+}
+
+fn find_glob(cache_path: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let root = Pattern::escape(&cache_path.to_string_lossy());
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::default()
+    };
+    let paths = glob_with(&format!("{root}/{pattern}"), options)
+        .wrap_err_with(|| format!("invalid path pattern: {pattern}"))?
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_file())
+        .collect();
+
+    Ok(paths)
+}
+
+/// symlinked directories aren't followed, they may point back into the archive
+fn find_executables(dir: &Path, name: &str) -> Result<Vec<PathBuf>> {
+    let mut found = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            found.extend(find_executables(&path, name)?);
+        } else if is_executable_named(&path, name) {
+            found.push(path);
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(target_os = "windows")]
+fn is_executable_named(path: &Path, name: &str) -> bool {
+    let exe = format!("{name}.exe");
+    path.file_name()
+        .is_some_and(|f| f.eq_ignore_ascii_case(&exe))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_executable_named(path: &Path, name: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.file_name().is_some_and(|f| f == name)
+        && path
+            .metadata()
+            .is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    fn create_file(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let file = fs::File::create(path).unwrap();
+        #[cfg(not(target_os = "windows"))]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_entry() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("abc");
+        assert!(Entry::load(&cache_path).unwrap().is_none());
+
+        let node = bin_key(Some("*/bin/node"), "node");
+        let npm = bin_key(None, "npm");
+        Entry {
+            bin_paths: BTreeMap::from([
+                (node.clone(), "node/bin/node".to_string()),
+                (npm.clone(), "node/bin/npm".to_string()),
+            ]),
+            ..Entry::default()
+        }
+        .save(&cache_path)
+        .unwrap();
+        let entry = Entry::load(&cache_path).unwrap().unwrap();
+        assert_eq!(entry.bin_paths[&node], "node/bin/node");
+        assert_eq!(entry.bin_paths[&npm], "node/bin/npm");
+        assert!(!entry.bin_paths.contains_key(&bin_key(None, "node")));
+    }
+
+    #[test]
+    fn test_find_bin() {
+        let dir = tempdir().unwrap();
+        let bin = Path::new("node-v18.7.0-linux-x64").join("bin").join("node");
+        create_file(&dir.path().join(&bin));
+        create_file(&dir.path().join("node-v18.7.0-linux-x64/bin/npm"));
+
+        let expected = bin.to_string_lossy();
+        assert_eq!(
+            find_bin(dir.path(), Some("*/bin/node"), "node").unwrap(),
+            expected
+        );
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(find_bin(dir.path(), None, "node").unwrap(), expected);
+        assert!(find_bin(dir.path(), Some("*/bin/*"), "node").is_err());
+        assert!(find_bin(dir.path(), None, "deno").is_err());
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_find_bin_symlinked_dir() {
+        let dir = tempdir().unwrap();
+        create_file(&dir.path().join("tool-v1/bin/tool"));
+        // like /usr/bin/X11 -> . in distro archives
+        std::os::unix::fs::symlink(".", dir.path().join("tool-v1/bin/X11")).unwrap();
+
+        assert_eq!(
+            find_bin(dir.path(), None, "tool").unwrap(),
+            "tool-v1/bin/tool"
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_manifest_symlinked_dirs() {
+        let dir = tempdir().unwrap();
+        create_file(&dir.path().join("lib/libtool.so"));
+        std::os::unix::fs::symlink("lib", dir.path().join("lib64")).unwrap();
+        std::os::unix::fs::symlink(".", dir.path().join("lib/current")).unwrap();
+
+        assert_eq!(
+            manifest(dir.path()).unwrap().keys().collect::<Vec<_>>(),
+            vec!["lib/libtool.so"]
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = tempdir().unwrap();
+        let bin = dir.path().join("bin/node");
+        let lib = dir.path().join("lib/node.so");
+        create_file(&bin);
+        create_file(&lib);
+        fs::write(&bin, "node").unwrap();
+        let name = Path::new("bin").join("node").to_string_lossy().to_string();
+        let mut entry = Entry {
+            files: manifest(dir.path()).unwrap(),
+            ..Entry::default()
+        };
+        assert!(entry.needs_full_verify(0));
+        entry.mark_verified();
+        assert!(!entry.needs_full_verify(60));
+        assert!(entry.needs_full_verify(0));
+        assert!(!entry.verify(dir.path(), None, false).unwrap());
+        assert!(entry.has_checksum(
+            dir.path(),
+            &bin,
+            "sha256:545ea538461003efdc8c81c244531b003f6f26cfccf6c0073b3239fdedf49446"
+        ));
+
+        // a touched file that still matches is rehashed once and its new mtime recorded
+        let mtime = fs::metadata(&bin).unwrap().modified().unwrap();
+        let touch = |mtime| {
+            fs::File::options()
+                .write(true)
+                .open(&bin)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap()
+        };
+        touch(mtime + std::time::Duration::from_secs(1));
+        assert!(entry.verify(dir.path(), None, false).unwrap());
+        assert!(!entry.verify(dir.path(), None, false).unwrap());
+        touch(mtime);
+        assert!(entry.verify(dir.path(), None, false).unwrap());
+
+        // same size and mtime is only caught by a full check
+        fs::write(&bin, "evil").unwrap();
+        touch(mtime);
+        entry.verify(dir.path(), None, false).unwrap();
+        assert!(entry.verify(dir.path(), None, true).is_err());
+        fs::write(&bin, "node").unwrap();
+        touch(mtime);
+
+        // a quick check of the binary ignores the other files
+        fs::write(&lib, "evil").unwrap();
+        entry.verify(dir.path(), Some(&name), false).unwrap();
+        assert!(entry.verify(dir.path(), None, false).is_err());
+        fs::write(&lib, "").unwrap();
+
+        fs::write(&bin, "node v2").unwrap();
+        assert!(entry.verify(dir.path(), Some(&name), false).is_err());
+        fs::remove_file(&bin).unwrap();
+        assert!(entry.verify(dir.path(), Some(&name), false).is_err());
+    }
+}
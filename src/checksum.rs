@@ -1,32 +1,270 @@
 use color_eyre::eyre::{eyre, Result};
 use color_eyre::owo_colors::OwoColorize;
 use color_eyre::Section;
-use sha2::{Digest, Sha256, Sha512};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::digest::{DynDigest, InvalidBufferSize};
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use std::io::Read;
 use std::path::Path;
 use std::{fs, io};
 
+/// supported checksum algorithms, usable as the `algo` in `algo:hex`
+pub const ALGORITHMS: [&str; 7] = [
+    "sha256", "sha512", "sha384", "sha224", "sha1", "md5", "blake3",
+];
+
+/// algorithms with known collision attacks, rejected by forbid_weak_checksums
+const WEAK_ALGORITHMS: [&str; 2] = ["md5", "sha1"];
+
 pub fn validate(filename: &Path, checksum: &str) -> Result<()> {
-    let (algo, expected) = split_checksum(checksum);
+    let mut hasher = Hasher::new(checksum)?;
+    io::copy(&mut fs::File::open(filename)?, &mut hasher)?;
 
-    let actual = match algo {
-        "sha256" => get_checksum::<Sha256>(filename)?,
-        "sha512" => get_checksum::<Sha512>(filename)?,
-        _ => return Err(eyre!("unsupported checksum algorithm: {}", algo)),
-    };
+    hasher.verify()
+}
+
+/// incrementally hashes data and compares it to a checksum like `sha256:abc123`
+pub struct Hasher {
+    digest: Box<dyn DynDigest + Send>,
+    expected: String,
+}
+
+impl Hasher {
+    pub fn new(checksum: &str) -> Result<Hasher> {
+        let (algo, expected) = split_checksum(checksum);
 
-    if actual != expected {
-        display_mismatch_err(&actual, expected)?;
+        Ok(Hasher {
+            digest: new_digest(algo)?,
+            expected: expected.to_lowercase(),
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    pub fn verify(self) -> Result<()> {
+        let actual = hex::encode(self.digest.finalize());
+        if actual != self.expected {
+            display_mismatch_err(&actual, &self.expected)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// incrementally computes a checksum like `sha256:abc123` without an expected value
+/// used by fetchers to hash downloads as they are written
+pub struct Digester {
+    algorithm: String,
+    digest: Box<dyn DynDigest + Send>,
+}
+
+impl Digester {
+    pub fn new(algo: &str) -> Result<Digester> {
+        Ok(Digester {
+            algorithm: algo.to_string(),
+            digest: new_digest(algo)?,
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    pub fn finalize(self) -> String {
+        format!("{}:{}", self.algorithm, hex::encode(self.digest.finalize()))
+    }
+}
+
+/// compares a checksum computed while downloading to the expected one
+pub fn verify(actual: &str, expected: &str) -> Result<()> {
+    let (actual_algo, actual) = split_checksum(actual);
+    let (expected_algo, expected) = split_checksum(expected);
+    if actual_algo != expected_algo {
+        return Err(eyre!(
+            "cannot compare {actual_algo} checksum to {expected_algo} checksum"
+        ));
+    }
+    if !actual.eq_ignore_ascii_case(expected) {
+        display_mismatch_err(actual, &expected.to_lowercase())?;
     }
 
     Ok(())
 }
 
-pub fn get_checksum<D: Digest + io::Write>(file: &Path) -> Result<String> {
-    let mut hasher = D::new();
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// hashes and counts everything that is read through it
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Option<Hasher>,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, hasher: Option<Hasher>) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher,
+            size: 0,
+        }
+    }
+
+    /// number of bytes read so far
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn into_hasher(self) -> Option<Hasher> {
+        self.hasher
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.size += n as u64;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+
+        Ok(n)
+    }
+}
+
+/// hex digest of a file using any of the supported `ALGORITHMS`
+pub fn get_checksum(file: &Path, algo: &str) -> Result<String> {
+    let mut digest = new_digest(algo)?;
     let mut file = fs::File::open(file)?;
-    io::copy(&mut file, &mut hasher)?;
+    let mut buf = [0; 8192];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => digest.update(&buf[..n]),
+        }
+    }
+
+    Ok(hex::encode(digest.finalize()))
+}
+
+pub fn algorithm(checksum: &str) -> &str {
+    split_checksum(checksum).0
+}
 
-    Ok(hex::encode(hasher.finalize()))
+pub fn is_weak(algo: &str) -> bool {
+    WEAK_ALGORITHMS.contains(&algo)
+}
+
+/// finds the checksum for `filename` in a checksum manifest like SHASUMS256.txt
+/// supports both `<hex>  <filename>` lines (algorithm inferred from the digest length)
+/// and BSD-style `SHA256 (<filename>) = <hex>` lines
+/// returns the checksum as `algo:hex`
+pub fn parse_manifest(manifest: &str, filename: &str) -> Option<String> {
+    manifest
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| parse_bsd_line(line).or_else(|| parse_gnu_line(line)))
+        .find(|(_, f, _)| {
+            let f = f.trim_start_matches('*');
+            f == filename || f.rsplit(['/', '\\']).next() == Some(filename)
+        })
+        .map(|(algo, _, hex)| format!("{algo}:{}", hex.to_lowercase()))
+}
+
+/// parses `SHA256 (<filename>) = <hex>` into (algo, filename, hex)
+fn parse_bsd_line(line: &str) -> Option<(String, &str, &str)> {
+    let (algo, rest) = line.split_once(" (")?;
+    let (filename, hex) = rest.rsplit_once(") = ")?;
+    let algo = algo.to_lowercase().replace('-', "");
+
+    match ALGORITHMS.contains(&algo.as_str()) {
+        true => Some((algo, filename, hex.trim())),
+        false => None,
+    }
+}
+
+/// parses `<hex>  <filename>` into (algo, filename, hex)
+fn parse_gnu_line(line: &str) -> Option<(String, &str, &str)> {
+    let (hex, filename) = line.split_once(char::is_whitespace)?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let algo = match hex.len() {
+        32 => "md5",
+        40 => "sha1",
+        56 => "sha224",
+        64 => "sha256",
+        96 => "sha384",
+        128 => "sha512",
+        _ => return None,
+    };
+
+    Some((algo.to_string(), filename.trim(), hex))
+}
+
+fn new_digest(algo: &str) -> Result<Box<dyn DynDigest + Send>> {
+    Ok(match algo {
+        "sha256" => Box::<Sha256>::default(),
+        "sha512" => Box::<Sha512>::default(),
+        "sha384" => Box::<Sha384>::default(),
+        "sha224" => Box::<Sha224>::default(),
+        "sha1" => Box::<Sha1>::default(),
+        "md5" => Box::<Md5>::default(),
+        "blake3" => Box::<Blake3>::default(),
+        _ => {
+            return Err(eyre!("unsupported checksum algorithm: {}", algo)
+                .suggestion(format!("use one of: {}", ALGORITHMS.join(", "))))
+        }
+    })
+}
+
+/// blake3's own api as a DynDigest, its digest trait impls are an unstable preview
+#[derive(Clone, Default)]
+struct Blake3(blake3::Hasher);
+
+impl DynDigest for Blake3 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize_into(mut self, buf: &mut [u8]) -> std::result::Result<(), InvalidBufferSize> {
+        self.finalize_into_reset(buf)
+    }
+
+    fn finalize_into_reset(
+        &mut self,
+        buf: &mut [u8],
+    ) -> std::result::Result<(), InvalidBufferSize> {
+        if buf.len() != blake3::OUT_LEN {
+            return Err(InvalidBufferSize);
+        }
+        buf.copy_from_slice(self.0.finalize().as_bytes());
+        self.0.reset();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn output_size(&self) -> usize {
+        blake3::OUT_LEN
+    }
+
+    fn box_clone(&self) -> Box<dyn DynDigest> {
+        Box::new(self.clone())
+    }
 }
 
 fn split_checksum(checksum: &str) -> (&str, &str) {
@@ -51,6 +289,7 @@ mod tests {
     use std::fs::File;
     use std::io::Write;
     use tempfile::tempdir;
+    use test_case::test_case;
 
     #[test]
     fn test_split_checksum() {
@@ -66,13 +305,86 @@ mod tests {
         let mut f = File::create(&file).unwrap();
         f.write_all(b"hello world").unwrap();
 
-        let checksum = get_checksum::<Sha256>(&file).unwrap();
+        let checksum = get_checksum(&file, "sha256").unwrap();
         assert_eq!(
             checksum,
             "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
         );
     }
 
+    #[test_case("sha1", "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed")]
+    #[test_case("sha224", "2f05477fc24bb4faefd86517156dafdecec45b8ad3cf2522a563582b")]
+    #[test_case("sha384", "fdbd8e75a67f29f701a4e040385e2e23986303ea10239211af907fcbb83578b3e417cb71ce646efd0819dd8c088de1bd")]
+    #[test_case("md5", "5eb63bbbe01eeed093cb22bb8f5acdc3")]
+    #[test_case(
+        "blake3",
+        "d74981efa70a0c880b8d8c1985d075dbcbf679b99a5f9914e5aaf96b831a9e24"
+    )]
+    fn test_algorithms(algo: &str, expected: &str) {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("test.txt");
+        fs::write(&file, b"hello world").unwrap();
+
+        assert_eq!(get_checksum(&file, algo).unwrap(), expected);
+        validate(&file, &format!("{algo}:{}", expected.to_uppercase())).unwrap();
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = "\
+# comment
+a03cb97533447a5005accef871b899df0e9da33d8a805675ac53715a534b3dcb  node-v18.8.0-darwin-arm64.tar.xz
+C4ADD613391E51406CAAD37FAFE12EF67EB53EEFF11F233F63CC65E643BF6700 *dist/node-v18.8.0-linux-x64.tar.xz
+SHA512 (tool.zip) = abc123
+MD5 (tool.tar.gz) = 5eb63bbbe01eeed093cb22bb8f5acdc3
+";
+        assert_eq!(
+            parse_manifest(manifest, "node-v18.8.0-darwin-arm64.tar.xz").unwrap(),
+            "sha256:a03cb97533447a5005accef871b899df0e9da33d8a805675ac53715a534b3dcb"
+        );
+        assert_eq!(
+            parse_manifest(manifest, "node-v18.8.0-linux-x64.tar.xz").unwrap(),
+            "sha256:c4add613391e51406caad37fafe12ef67eb53eeff11f233f63cc65e643bf6700"
+        );
+        assert_eq!(
+            parse_manifest(manifest, "tool.zip").unwrap(),
+            "sha512:abc123"
+        );
+        assert_eq!(
+            parse_manifest(manifest, "tool.tar.gz").unwrap(),
+            "md5:5eb63bbbe01eeed093cb22bb8f5acdc3"
+        );
+        assert!(parse_manifest(manifest, "node-v18.8.0-win-x64.zip").is_none());
+    }
+
+    #[test]
+    fn test_digester() {
+        let mut digester = Digester::new("sha256").unwrap();
+        digester.update(b"hello ");
+        digester.update(b"world");
+        let checksum = digester.finalize();
+        assert_eq!(
+            checksum,
+            "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+
+        verify(
+            &checksum,
+            "sha256:B94D27B9934D3E08A52E52D7DA7DABFAC484EFE37A5380EE9088F7ACE2EFCDE9",
+        )
+        .unwrap();
+        assert!(verify(&checksum, "sha256:invalid").is_err());
+        assert!(verify(&checksum, "sha512:b94d27").is_err());
+    }
+
+    #[test]
+    fn test_is_weak() {
+        assert!(is_weak("md5"));
+        assert!(is_weak("sha1"));
+        assert!(!is_weak("sha256"));
+        assert!(!is_weak("blake3"));
+    }
+
     #[test]
     fn test_validate() {
         let dir = tempdir().unwrap();
@@ -87,4 +399,17 @@ mod tests {
         .unwrap();
         assert!(validate(&file, "sha256:invalid").is_err());
     }
+
+    #[test]
+    fn test_hashing_reader() {
+        let hasher =
+            Hasher::new("sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+                .unwrap();
+        let mut reader = HashingReader::new(&b"hello world"[..], Some(hasher));
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+
+        assert_eq!(reader.size(), 11);
+        reader.into_hasher().unwrap().verify().unwrap();
+        assert!(Hasher::new("md4:abc").is_err());
+    }
 }
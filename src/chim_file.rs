@@ -1,24 +1,59 @@
-use crate::platform::split_platform_name;
-use color_eyre::eyre::Context;
+use crate::env::{self, Expansions};
+use crate::platform::PlatformKey;
+use crate::schema;
+use color_eyre::eyre::{eyre, Context};
 use color_eyre::{Result, Section};
+use itertools::Itertools;
 use serde_derive::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
 
 #[derive(Debug, Deserialize, Default)]
 pub struct ChimFile {
     #[serde(default)]
+    #[allow(dead_code)]
     pub debug: bool,
 
     #[serde(default)]
     pub quiet: bool,
 
+    /// substituted for `{version}`
+    pub version: Option<String>,
+    /// substituted for `{ext}`
+    pub ext: Option<String>,
+    /// upstream names for chim's os names, e.g.: `macos = "darwin"`
+    #[serde(default)]
+    pub os_map: HashMap<String, String>,
+    /// upstream names for chim's arch names, e.g.: `x86_64 = "x64"`
+    #[serde(default)]
+    pub arch_map: HashMap<String, String>,
+
     pub url: Option<String>,
     pub path: Option<String>,
     pub checksum: Option<String>,
-    pub archive: Option<String>,
+    pub checksum_url: Option<String>,
+    pub checksum_url_checksum: Option<String>,
+    pub signature_url: Option<String>,
+    pub public_key: Option<String>,
+    pub bin_checksum: Option<String>,
+    pub archive: Option<ArchiveChain>,
+    pub inner: Option<String>,
+    pub extract: Option<Vec<String>>,
+    pub strip_components: Option<usize>,
     pub execvp: Option<bool>,
+    /// environment variables for the binary, `{install_dir}` is where the artifact was extracted
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// directories in the install dir added to the front of PATH, e.g.: `["bin"]`
+    pub path_prepend: Option<Vec<String>>,
+    /// arguments passed before the user's, `{chim_dir}` is the directory of the chim
+    pub args: Option<Vec<String>>,
+    /// arguments passed after the user's
+    pub args_append: Option<Vec<String>>,
+    /// directory the binary runs in: `chim_dir`, `git_root` or a path relative to the chim
+    pub cwd: Option<String>,
 
     // s3
     pub aws_profile: Option<String>,
@@ -33,19 +68,41 @@ pub struct ChimFile {
     pub pre_execute: Option<String>,
     pub post_execute: Option<String>,
 
+    #[allow(dead_code)]
     pub pre_execute_interval: Option<i64>,
 
     #[serde(flatten)]
     pub platforms: HashMap<String, Platform>,
+
+    /// strings with secret environment variables expanded into them, see `Expansions::redacted`
+    #[serde(skip)]
+    pub redacted: HashMap<String, String>,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq, Hash, Default)]
 pub struct Platform {
+    pub version: Option<String>,
+    pub ext: Option<String>,
     pub url: Option<String>,
     pub path: Option<String>,
     pub checksum: Option<String>,
-    pub archive: Option<String>,
+    pub checksum_url: Option<String>,
+    pub checksum_url_checksum: Option<String>,
+    pub signature_url: Option<String>,
+    pub public_key: Option<String>,
+    pub bin_checksum: Option<String>,
+    pub archive: Option<ArchiveChain>,
+    pub inner: Option<String>,
+    pub extract: Option<Vec<String>>,
+    pub strip_components: Option<usize>,
     pub execvp: Option<bool>,
+    /// merged over the top-level `[env]`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub path_prepend: Option<Vec<String>>,
+    pub args: Option<Vec<String>>,
+    pub args_append: Option<Vec<String>>,
+    pub cwd: Option<String>,
 
     // s3
     pub aws_profile: Option<String>,
@@ -53,29 +110,360 @@ pub struct Platform {
     pub aws_secret_access_key: Option<String>,
     pub aws_access_token: Option<String>,
     pub aws_region: Option<String>,
+
+    /// platforms whose builds run on this one if it has no url of its own, e.g.: under rosetta
+    pub fallback: Option<Vec<String>>,
+}
+
+/// a platform's merged settings and the platform whose build they describe
+#[derive(Debug, Default)]
+pub struct ResolvedPlatform {
+    pub platform: Platform,
+    pub os: String,
+    pub arch: String,
+    pub libc: Option<String>,
+    /// the matching tables, most specific first
+    pub keys: Vec<String>,
+}
+
+const HOOKS: [&str; 4] = ["pre_fetch", "pre_extract", "pre_execute", "post_execute"];
+
+/// top-level tables that are settings rather than platforms
+pub const SETTING_TABLES: [&str; 3] = ["os_map", "arch_map", "env"];
+
+/// a single archive format or a chain of formats for nested archives
+/// e.g.: `archive = "tar.gz"` or `archive = ["zip", "tar.gz"]`
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum ArchiveChain {
+    Single(String),
+    Nested(Vec<String>),
+}
+
+impl ArchiveChain {
+    /// the archive formats from the outermost layer in
+    pub fn formats(&self) -> Vec<String> {
+        match self {
+            ArchiveChain::Single(format) => vec![format.clone()],
+            ArchiveChain::Nested(formats) => formats.clone(),
+        }
+    }
 }
 
 impl ChimFile {
     pub fn from_file(filename: &Path) -> Result<ChimFile> {
-        let body = fs::read_to_string(filename).suggestion("ensure file exists and can be read")?;
-        ChimFile::from_str(body).wrap_err("error parsing toml")
+        let (mut table, _) = load(filename)?;
+        schema::remove_unknown(&mut table);
+        let mut expansions = Expansions::default();
+        expand_env(&mut table, &mut expansions);
+        if !expansions.unset.is_empty() {
+            return Err(eyre!(
+                "unset environment variables: {}",
+                expansions.unset.iter().join(", ")
+            )
+            .suggestion("set them or add a default with ${VAR:-default}"));
+        }
+        let mut chim_file: ChimFile = toml::Value::Table(table)
+            .try_into()
+            .wrap_err("error parsing toml")
+            .suggestion("Ensure chim is valid TOML.")?;
+        chim_file.redacted = expansions.redacted;
+
+        Ok(chim_file)
+    }
+
+    /// the settings of every table matching the platform
+    /// if none of them has a url, the platform's `fallback` is used instead
+    pub fn resolve_platform(
+        &self,
+        os: &str,
+        arch: &str,
+        libc: Option<&str>,
+    ) -> Result<ResolvedPlatform> {
+        let native = self.merge_platforms(os, arch, libc)?;
+        if native.platform.url.is_some() {
+            return Ok(native);
+        }
+        let (fallbacks, explicit) = match &native.platform.fallback {
+            Some(fallbacks) => (fallbacks.clone(), true),
+            None => (default_fallbacks(os, arch), false),
+        };
+        let mut candidates = vec![];
+        for fallback in fallbacks {
+            let key = PlatformKey::parse(&fallback)?;
+            let (Some(fallback_os), Some(fallback_arch)) = (&key.os, &key.arch) else {
+                return Err(eyre!(
+                    "fallback must be a platform like linux-x64, got {fallback}"
+                ));
+            };
+            let fallback_libc = key.libc.as_deref().or(libc);
+            let candidate = self.merge_platforms(fallback_os, fallback_arch, fallback_libc)?;
+            if candidate.platform.url.is_some() {
+                debug!("using {key} build on {os}-{arch}");
+                return Ok(candidate);
+            }
+            candidates.push(candidate);
+        }
+        // an explicit fallback renders the top-level url for the fallback's platform
+        Ok(match (explicit, candidates.into_iter().next()) {
+            (true, Some(candidate)) => candidate,
+            _ => native,
+        })
+    }
+
+    fn merge_platforms(
+        &self,
+        os: &str,
+        arch: &str,
+        libc: Option<&str>,
+    ) -> Result<ResolvedPlatform> {
+        let mut matches = vec![];
+        for (name, platform) in &self.platforms {
+            let key = PlatformKey::parse(name)?;
+            if key.matches(os, arch, libc) {
+                matches.push((key, platform));
+            }
+        }
+        matches.sort_by_key(|(key, _)| std::cmp::Reverse(key.specificity()));
+
+        Ok(ResolvedPlatform {
+            platform: matches
+                .iter()
+                .fold(Platform::default(), |merged, (_, p)| merged.or(p)),
+            os: os.to_string(),
+            arch: arch.to_string(),
+            libc: libc.map(String::from),
+            keys: matches.iter().map(|(key, _)| key.to_string()).collect(),
+        })
+    }
+}
+
+impl Platform {
+    /// fills the settings that aren't set from `other`
+    /// a checksum belongs to a url so it isn't inherited by a table with its own url
+    fn or(self, other: &Platform) -> Platform {
+        let checksum = match self.url {
+            Some(_) => self.checksum,
+            None => self.checksum.or_else(|| other.checksum.clone()),
+        };
+        let mut env = other.env.clone();
+        env.extend(self.env);
+        macro_rules! or {
+            ($($field:ident),*) => {
+                Platform {
+                    checksum,
+                    env,
+                    $($field: self.$field.or_else(|| other.$field.clone()),)*
+                }
+            };
+        }
+        or!(
+            version,
+            ext,
+            url,
+            path,
+            checksum_url,
+            checksum_url_checksum,
+            signature_url,
+            public_key,
+            bin_checksum,
+            archive,
+            inner,
+            extract,
+            strip_components,
+            execvp,
+            path_prepend,
+            args,
+            args_append,
+            cwd,
+            aws_profile,
+            aws_access_key_id,
+            aws_secret_access_key,
+            aws_access_token,
+            aws_region,
+            fallback
+        )
+    }
+}
+
+/// emulated or compatible platforms used when there is no native build and no `fallback`
+fn default_fallbacks(os: &str, arch: &str) -> Vec<String> {
+    match (os, arch) {
+        ("macos", "aarch64") => vec!["macos-x86_64".to_string()],
+        ("windows", "aarch64") => vec!["windows-x86_64".to_string()],
+        // armv7 runs armv6 builds
+        (os, "armv7") => vec![format!("{os}-arm")],
+        _ => vec![],
+    }
+}
+
+/// expands environment variables in every string except hooks which the shell expands itself
+fn expand_env(table: &mut Table, expansions: &mut Expansions) {
+    fn expand_value(value: &mut Value, expansions: &mut Expansions) {
+        match value {
+            Value::String(s) => *s = env::expand(s, expansions),
+            Value::Array(values) => values.iter_mut().for_each(|v| expand_value(v, expansions)),
+            Value::Table(table) => expand_env(table, expansions),
+            _ => {}
+        }
+    }
+    for (key, value) in table.iter_mut() {
+        if !HOOKS.contains(&key.as_str()) {
+            expand_value(value, expansions);
+        }
+    }
+}
+
+/// the file each setting came from, keyed by dotted path, e.g.: `linux-x86_64.url`
+pub type Sources = BTreeMap<String, PathBuf>;
+
+/// reads a chim and everything it `extends`, the chim's own settings override its bases
+/// platform tables are keyed by their normalized name so `darwin-arm64` merges into `macos-aarch64`
+pub fn load(filename: &Path) -> Result<(Table, Sources)> {
+    load_layer(filename, &mut vec![])
+}
+
+fn load_layer(filename: &Path, stack: &mut Vec<PathBuf>) -> Result<(Table, Sources)> {
+    let canonical = fs::canonicalize(filename)
+        .wrap_err_with(|| format!("error reading {}", filename.display()))
+        .suggestion("ensure file exists and can be read")?;
+    if stack.contains(&canonical) {
+        let chain = stack
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .join(" -> ");
+        return Err(eyre!("extends cycle: {chain}"));
     }
+    trace!("reading {:?}", filename);
+    let body = fs::read_to_string(filename).suggestion("ensure file exists and can be read")?;
+    let mut table = body
+        .parse::<Table>()
+        .wrap_err_with(|| format!("error parsing {}", filename.display()))
+        .suggestion("Ensure chim is valid TOML.")?;
+    schema::validate(&body, filename)?;
+    let bases = match table.remove("extends") {
+        None => vec![],
+        Some(Value::String(base)) => vec![base],
+        Some(Value::Array(bases)) => bases
+            .into_iter()
+            .map(|base| match base {
+                Value::String(base) => Ok(base),
+                _ => Err(eyre!("extends must only contain paths")),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => {
+            return Err(eyre!("extends must be a path or a list of paths")
+                .section(format!("Chim: {}", filename.display())))
+        }
+    };
+    normalize_platforms(&mut table)
+        .wrap_err_with(|| format!("error parsing {}", filename.display()))?;
+
+    let dir = filename.parent().unwrap_or(Path::new("."));
+    let mut merged = Table::new();
+    let mut sources = Sources::new();
+    stack.push(canonical);
+    for base in bases {
+        let base_path = dir.join(base);
+        let (mut base, base_sources) = load_layer(&base_path, stack)
+            .wrap_err_with(|| format!("error loading base of {}", filename.display()))?;
+        if let Some(base_dir) = std::path::absolute(&base_path)?.parent() {
+            rebase_paths(&mut base, base_dir, false);
+        }
+        merge(&mut merged, base);
+        sources.extend(base_sources);
+    }
+    stack.pop();
+    for (key, _) in flatten(&table) {
+        sources.insert(key, filename.to_path_buf());
+    }
+    merge(&mut merged, table);
+
+    Ok((merged, sources))
+}
 
-    fn from_str(s: String) -> Result<ChimFile> {
-        let mut c: ChimFile = toml::from_str(&s).suggestion("Ensure chim is valid TOML.")?;
-        c.normalize_platforms();
+/// makes paths in a base relative to the base instead of the chim that extends it
+/// `path` is only a file beside the chim if the table or the chim uses a local url
+/// `cwd = "chim_dir"`, `cwd = "git_root"` and `{chim_dir}` still refer to the chim being run
+fn rebase_paths(table: &mut Table, dir: &Path, local: bool) {
+    let local = match table.get("url").and_then(Value::as_str) {
+        Some(url) => url.split(':').next() == Some("local"),
+        None => local,
+    };
+    for (key, value) in table.iter_mut() {
+        let value = match value {
+            Value::Table(platform) if !SETTING_TABLES.contains(&key.as_str()) => {
+                rebase_paths(platform, dir, local);
+                continue;
+            }
+            Value::String(value) => value,
+            _ => continue,
+        };
+        let rebased = match key.as_str() {
+            "path" if local => Some(dir.join(value.as_str())),
+            "checksum_url" | "signature_url" => value
+                .strip_prefix("local:")
+                .map(|path| PathBuf::from(format!("local:{}", dir.join(path).display()))),
+            "public_key" => Some(dir.join(value.as_str())).filter(|path| path.is_file()),
+            "cwd" if value != "chim_dir" && value != "git_root" => Some(dir.join(value.as_str())),
+            _ => None,
+        };
+        if let Some(rebased) = rebased {
+            *value = rebased.to_string_lossy().to_string();
+        }
+    }
+}
 
-        Ok(c)
+/// deep-merges `over` into `base`, values other than tables are replaced
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
     }
+}
 
-    fn normalize_platforms(&mut self) {
-        let keys = self.platforms.keys().cloned().collect::<Vec<String>>();
-        for k in keys {
-            let (os, arch) = split_platform_name(&k.to_lowercase());
-            let platform = self.platforms.remove(&k).unwrap();
-            self.platforms.insert(format!("{os}-{arch}"), platform);
+/// every value that isn't a table with its dotted path, top-level settings first
+pub fn flatten(table: &Table) -> Vec<(String, &Value)> {
+    let mut values = vec![];
+    let mut tables = vec![];
+    for (key, value) in table {
+        match value {
+            Value::Table(table) => tables.extend(
+                flatten(table)
+                    .into_iter()
+                    .map(|(k, v)| (format!("{key}.{k}"), v)),
+            ),
+            value => values.push((key.clone(), value)),
         }
     }
+    values.extend(tables);
+
+    values
+}
+
+/// true if `key` names a top-level table holding a platform
+pub fn is_platform(key: &str, value: &Value) -> bool {
+    value.is_table() && !SETTING_TABLES.contains(&key)
+}
+
+fn normalize_platforms(table: &mut Table) -> Result<()> {
+    let keys = table
+        .iter()
+        .filter(|(k, v)| is_platform(k, v))
+        .map(|(k, _)| k.clone())
+        .collect_vec();
+    for k in keys {
+        let key = PlatformKey::parse(&k)?;
+        let platform = table.remove(&k).unwrap();
+        merge(table, Table::from_iter([(key.to_string(), platform)]));
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -85,16 +473,141 @@ mod tests {
 
     #[test]
     fn test_normalize_platforms() {
-        let mut cf = ChimFile {
-            platforms: vec![("darwin-arm64".to_string(), Platform::default())]
-                .into_iter()
-                .collect(),
-            ..ChimFile::default()
-        };
+        let mut table: Table = toml::from_str(
+            r#"
+            [darwin-arm64]
+            url = "https://example.com/tool-darwin-arm64"
+            [macos-aarch64]
+            path = "tool"
+            [os_map]
+            macos = "darwin"
+            "#,
+        )
+        .unwrap();
+
+        normalize_platforms(&mut table).unwrap();
+
+        assert_eq!(
+            table.keys().collect_vec(),
+            ["macos-aarch64", "os_map"].iter().collect_vec()
+        );
+        assert_eq!(table["macos-aarch64"].as_table().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_normalize_platforms_abis() {
+        let mut table: Table = toml::from_str(
+            r#"
+            [linux-armv6]
+            url = "https://example.com/tool-armv6"
+            [linux-armv7]
+            url = "https://example.com/tool-armv7"
+            [linux-ppc64]
+            url = "https://example.com/tool-ppc64"
+            [linux-ppc64le]
+            url = "https://example.com/tool-ppc64le"
+            "#,
+        )
+        .unwrap();
+
+        normalize_platforms(&mut table).unwrap();
+
+        assert_eq!(
+            table.keys().collect_vec(),
+            [
+                "linux-arm",
+                "linux-armv7",
+                "linux-powerpc64",
+                "linux-powerpc64le"
+            ]
+            .iter()
+            .collect_vec()
+        );
+    }
+
+    #[test]
+    fn test_resolve_armv7_fallback() {
+        let chim_file: ChimFile = toml::from_str(
+            r#"
+            [linux-armel]
+            url = "https://example.com/tool-armv6"
+            "#,
+        )
+        .unwrap();
+        let resolved = chim_file.resolve_platform("linux", "armv7", None).unwrap();
+        assert_eq!(
+            resolved.platform.url.as_deref(),
+            Some("https://example.com/tool-armv6")
+        );
+        assert_eq!(resolved.arch, "arm");
+    }
+
+    #[test]
+    fn test_resolve_checksum() {
+        let chim_file: ChimFile = toml::from_str(
+            r#"
+            [linux]
+            url = "https://example.com/tool-linux.tar.gz"
+            checksum = "sha256:abc"
+            [linux-x64]
+            url = "https://example.com/tool-linux-x64.tar.gz"
+            [linux-arm64]
+            path = "bin/tool"
+            "#,
+        )
+        .unwrap();
+
+        let x64 = chim_file.resolve_platform("linux", "x86_64", None).unwrap();
+        assert_eq!(x64.platform.checksum, None);
+        let arm = chim_file
+            .resolve_platform("linux", "aarch64", None)
+            .unwrap();
+        assert_eq!(arm.platform.checksum.as_deref(), Some("sha256:abc"));
+    }
+
+    #[test]
+    fn test_extends() {
+        let dir = Path::new("test/fixtures/extends");
+        let chim_file = ChimFile::from_file(&dir.join("tool")).unwrap();
+        assert_eq!(chim_file.aws_profile.unwrap(), "tools");
+        assert_eq!(chim_file.aws_region.unwrap(), "eu-west-1");
+        let linux = &chim_file.platforms["linux-x86_64"];
+        assert_eq!(
+            linux.url.as_deref(),
+            Some("s3://tools/tool-linux-x64.tar.gz")
+        );
+        assert_eq!(linux.checksum.as_deref(), Some("sha256:abc"));
+
+        let (_, sources) = load(&dir.join("tool")).unwrap();
+        assert_eq!(sources["aws_profile"], dir.join("base.chim.toml"));
+        assert_eq!(sources["aws_region"], dir.join("tool"));
+        assert_eq!(sources["linux-x86_64.checksum"], dir.join("tool"));
+        assert_eq!(sources["linux-x86_64.url"], dir.join("base.chim.toml"));
 
-        cf.normalize_platforms();
+        // relative paths in a base are relative to the base
+        let shared = std::path::absolute(dir.join("shared")).unwrap();
+        let (chim, _) = load(&dir.join("shared_tool")).unwrap();
+        let local = |file: &str| format!("local:{}", shared.join(file).display());
+        assert_eq!(
+            chim["checksum_url"].as_str().unwrap(),
+            local("SHASUMS256.txt")
+        );
+        assert_eq!(
+            chim["signature_url"].as_str().unwrap(),
+            local("tool.tar.gz.minisig")
+        );
+        let path = |file: &str| shared.join(file).to_string_lossy().to_string();
+        assert_eq!(chim["public_key"].as_str().unwrap(), path("minisign.pub"));
+        assert_eq!(chim["cwd"].as_str().unwrap(), path("work"));
+        assert_eq!(chim["linux"]["path"].as_str().unwrap(), path("bin/tool"));
+        // paths inside an archive and chim_dir stay as they are
+        assert_eq!(
+            chim["macos"]["path"].as_str().unwrap(),
+            "tool-macos/bin/tool"
+        );
+        assert_eq!(chim["windows"]["cwd"].as_str().unwrap(), "chim_dir");
 
-        assert_eq!(cf.platforms.len(), 1);
-        assert!(cf.platforms.contains_key("macos-aarch64"));
+        let err = ChimFile::from_file(&dir.join("cycle_a")).unwrap_err();
+        assert!(format!("{err:?}").contains("extends cycle"));
     }
 }
@@ -1,12 +1,24 @@
-use crate::checksum::get_checksum;
+use crate::app::App;
+use crate::checksum::{self, get_checksum, ALGORITHMS};
+use crate::chim_file::{self, SETTING_TABLES};
 use crate::config::Config;
-use crate::fetchers;
-use crate::platform::split_platform_name;
-use color_eyre::eyre::Result;
-use sha2::Sha256;
+use crate::fetchers::{self, Progress};
+use crate::lockfile::{LockEntry, Lockfile, DEFAULT_PLATFORM};
+use crate::platform::PlatformKey;
+use crate::template;
+use color_eyre::eyre::{eyre, Result};
+use color_eyre::owo_colors::OwoColorize;
+use color_eyre::{Section, SectionExt};
+use indicatif::MultiProgress;
+use itertools::Itertools;
 use std::fs;
+use std::future::Future;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tempfile::tempdir;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use toml::Table;
 use toml_edit::{value, Document};
 
 #[derive(Debug, clap::Args)]
@@ -14,25 +26,377 @@ use toml_edit::{value, Document};
 pub struct Args {
     #[clap(help = "The path to the chim file to update")]
     chim_file: PathBuf,
+
+    #[clap(short, long, default_value = "sha256", value_parser = ALGORITHMS, help = "The checksum algorithm to use")]
+    algorithm: String,
+
+    #[clap(
+        long,
+        help = "Write resolved urls and checksums to chim.lock instead of the chim"
+    )]
+    lock: bool,
+
+    #[clap(
+        long,
+        conflicts_with = "lock",
+        help = "Verify the recorded checksums without modifying anything, exits non-zero on failure"
+    )]
+    check: bool,
+
+    #[clap(
+        short,
+        long = "platform",
+        help = "Only update these platforms, e.g.: linux-x64 (repeatable)"
+    )]
+    platforms: Vec<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "check",
+        help = "Only fetch platforms that don't have a checksum yet"
+    )]
+    missing_only: bool,
+
+    #[clap(
+        short,
+        long,
+        default_value = "4",
+        help = "The number of artifacts to fetch at once"
+    )]
+    jobs: usize,
+}
+
+/// something in the chim with its own url: a platform table or the top-level url
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    /// the platform table, None for the top-level url
+    table: Option<String>,
+}
+
+impl Target {
+    fn name(&self) -> &str {
+        self.table.as_deref().unwrap_or(DEFAULT_PLATFORM)
+    }
+
+    /// the normalized platform name used as the key in chim.lock
+    fn platform(&self) -> String {
+        self.name().to_string()
+    }
+
+    /// wildcards in the table's key only match tables that are at least as general
+    fn config(&self, filename: &Path) -> Result<Config> {
+        let key = PlatformKey::parse(self.name())?;
+        Config::from_chim_file(
+            filename,
+            key.os.as_deref().unwrap_or(DEFAULT_PLATFORM),
+            key.arch.as_deref().unwrap_or(DEFAULT_PLATFORM),
+            key.libc.as_deref(),
+        )
+    }
+
+    /// true if the chim or one of its bases has a checksum for the target
+    fn has_checksum(&self, chim: &Table) -> bool {
+        match &self.table {
+            Some(table) => chim.get(table).and_then(|t| t.get("checksum")).is_some(),
+            None => chim.get("checksum").is_some(),
+        }
+    }
+
+    /// the table may be spelled differently in the chim, e.g.: `[darwin-arm64]`
+    /// or not exist yet if it is inherited from a base or the url is templated
+    fn set_checksum(&self, doc: &mut Document, checksum: String) {
+        match &self.table {
+            Some(table) => {
+                let key = doc
+                    .iter()
+                    .find(|(k, v)| {
+                        v.is_table()
+                            && !SETTING_TABLES.contains(k)
+                            && normalize_platform(k).is_ok_and(|k| k == *table)
+                    })
+                    .map(|(k, _)| k.to_string())
+                    .unwrap_or_else(|| table.clone());
+                doc.entry(&key).or_insert_with(toml_edit::table)["checksum"] = value(checksum)
+            }
+            None => doc["checksum"] = value(checksum),
+        }
+    }
 }
 
 pub async fn run(args: Args) -> Result<()> {
     let filename = &args.chim_file;
-    let mut doc = read(filename)?;
+    let doc = read(filename)?;
     trace!("{}", doc.to_string());
+    let (chim, _) = chim_file::load(filename)?;
+    let targets = get_targets(&chim, &args.platforms)?;
 
-    for (platform, values) in doc.iter_mut() {
-        if !values.is_table() {
-            continue;
+    if args.check {
+        check(filename, targets, args.jobs).await
+    } else if args.lock {
+        lock(filename, targets, &args).await
+    } else {
+        update(filename, doc, &chim, targets, &args).await
+    }
+}
+
+/// writes the checksums into the chim, preserving comments and formatting
+async fn update(
+    filename: &Path,
+    mut doc: Document,
+    chim: &Table,
+    targets: Vec<Target>,
+    args: &Args,
+) -> Result<()> {
+    let targets = targets
+        .into_iter()
+        .filter(|t| !(args.missing_only && t.has_checksum(chim)))
+        .collect_vec();
+    let algorithm = args.algorithm.clone();
+    let results = run_jobs(filename, &targets, args.jobs, move |config, progress| {
+        let algorithm = algorithm.clone();
+        async move { fetch_checksum(&config, &algorithm, progress).await }
+    })
+    .await?;
+
+    let mut errors = vec![];
+    for (target, result) in targets.iter().zip_eq(results) {
+        match result {
+            Ok(entry) => target.set_checksum(&mut doc, entry.checksum),
+            Err(err) => errors.push(format!("{}: {}", target.name(), err)),
         }
-        values["checksum"] = value(fetch_checksum(filename, &platform).await?);
     }
 
     debug!("{}", doc.to_string());
     write(filename, doc)?;
-
     info!("updated checksums in {:?}", filename);
-    Ok(())
+
+    failed(errors)
+}
+
+/// updates the entries for the chim in chim.lock, leaving the chim itself untouched
+async fn lock(filename: &Path, targets: Vec<Target>, args: &Args) -> Result<()> {
+    let lockfile_path = Lockfile::path(filename);
+    let mut lockfile = Lockfile::load(&lockfile_path)?;
+    let name = filename.file_name().unwrap().to_string_lossy().to_string();
+    let mut entries = match args.missing_only || !args.platforms.is_empty() {
+        true => lockfile.entries(&name),
+        false => Default::default(),
+    };
+    let targets = targets
+        .into_iter()
+        .filter(|t| !(args.missing_only && entries.contains_key(&t.platform())))
+        .collect_vec();
+    let algorithm = args.algorithm.clone();
+    let results = run_jobs(filename, &targets, args.jobs, move |config, progress| {
+        let algorithm = algorithm.clone();
+        async move { fetch_checksum(&config, &algorithm, progress).await }
+    })
+    .await?;
+
+    let mut errors = vec![];
+    for (target, result) in targets.iter().zip_eq(results) {
+        match result {
+            Ok(entry) => {
+                entries.insert(target.platform(), entry);
+            }
+            Err(err) => errors.push(format!("{}: {}", target.name(), err)),
+        }
+    }
+
+    lockfile.set(&name, entries);
+    lockfile.save(&lockfile_path)?;
+    info!("updated {:?}", lockfile_path);
+
+    failed(errors)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Missing,
+    Mismatch { expected: String, actual: String },
+}
+
+/// fetches every platform's artifact and compares it to the checksum in the chim,
+/// chim.lock or checksum_url manifest
+async fn check(filename: &Path, targets: Vec<Target>, jobs: usize) -> Result<()> {
+    let results = run_jobs(filename, &targets, jobs, |config, progress| async move {
+        check_platform(&config, progress).await
+    })
+    .await?;
+
+    let mut failed = 0;
+    for (target, result) in targets.iter().zip_eq(results) {
+        let status = match result {
+            Ok(Status::Ok) => format!("{}", "OK".green()),
+            Ok(Status::Missing) => {
+                failed += 1;
+                format!("{}", "missing".yellow())
+            }
+            Ok(Status::Mismatch { expected, actual }) => {
+                failed += 1;
+                format!("{} expected {} got {}", "mismatch".red(), expected, actual)
+            }
+            Err(err) => {
+                failed += 1;
+                format!("{} {}", "error".red(), err)
+            }
+        };
+        println!("{:<20} {}", target.name(), status);
+    }
+
+    match failed {
+        0 => Ok(()),
+        n => Err(
+            eyre!("{n} platform(s) failed the checksum check").suggestion(format!(
+                "run `chim checksums {}` to update them",
+                filename.display()
+            )),
+        ),
+    }
+}
+
+/// platforms without a checksum are reported as missing without downloading their artifact
+async fn check_platform(config: &Config, progress: Progress) -> Result<Status> {
+    let expected = match App::new(config)?.get_checksum().await? {
+        Some(expected) => expected,
+        None => return Ok(Status::Missing),
+    };
+    let algorithm = checksum::algorithm(&expected);
+    let actual = fetch_checksum(config, algorithm, progress).await?.checksum;
+
+    Ok(match checksum::verify(&actual, &expected) {
+        Ok(()) => Status::Ok,
+        Err(_) => Status::Mismatch { expected, actual },
+    })
+}
+
+/// the top-level url followed by every platform table with its own url, including tables from bases
+/// a top-level url templated with `{os}`, `{arch}` or `{ext}` has a checksum per os-arch table instead
+/// if platforms are given, only those are returned, falling back to the top-level url
+fn get_targets(chim: &Table, platforms: &[String]) -> Result<Vec<Target>> {
+    let templated = chim
+        .get("url")
+        .and_then(|url| url.as_str())
+        .is_some_and(template::is_platform_specific);
+    let default = chim
+        .get("url")
+        .filter(|_| !templated)
+        .map(|_| Target { table: None });
+    let tables = chim
+        .iter()
+        .filter(|(k, v)| chim_file::is_platform(k, v))
+        .filter(|(k, v)| match v.get("url") {
+            Some(_) => true,
+            // wildcard tables and platforms using a fallback build have no artifact of their own
+            None => templated && v.get("fallback").is_none() && is_concrete(k),
+        })
+        .map(|(k, _)| Target {
+            table: Some(k.to_string()),
+        })
+        .collect_vec();
+    if platforms.is_empty() {
+        if templated && tables.is_empty() {
+            return Err(eyre!("url is templated but there are no platform tables")
+                .suggestion("add a table like [linux-x64] for each platform or use --platform"));
+        }
+        return Ok(default.into_iter().chain(tables).collect());
+    }
+
+    let mut targets = vec![];
+    for platform in platforms {
+        let key = PlatformKey::parse(platform)?;
+        let platform = key.to_string();
+        let table = tables
+            .iter()
+            .filter_map(|t| Some((t, PlatformKey::parse(t.name()).ok()?)))
+            .filter(|(t, k)| t.platform() == platform || matches_key(k, &key))
+            .max_by_key(|(_, k)| k.specificity())
+            .map(|(t, _)| t);
+        let target = match table {
+            Some(target) => target.clone(),
+            None if templated => Target {
+                table: Some(platform),
+            },
+            None => default
+                .clone()
+                .ok_or_else(|| eyre!("no url for platform {platform}"))?,
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    Ok(targets)
+}
+
+fn normalize_platform(platform: &str) -> Result<String> {
+    Ok(PlatformKey::parse(platform)?.to_string())
+}
+
+fn is_concrete(platform: &str) -> bool {
+    PlatformKey::parse(platform).is_ok_and(|k| k.is_concrete())
+}
+
+/// true if the table `key` applies to the concrete platform `platform`
+fn matches_key(key: &PlatformKey, platform: &PlatformKey) -> bool {
+    match (&platform.os, &platform.arch) {
+        (Some(os), Some(arch)) => key.matches(os, arch, platform.libc.as_deref()),
+        _ => false,
+    }
+}
+
+/// runs `job` for every target with at most `jobs` running at once
+/// the results are in the same order as `targets`
+async fn run_jobs<T, F, Fut>(
+    filename: &Path,
+    targets: &[Target],
+    jobs: usize,
+    job: F,
+) -> Result<Vec<Result<T>>>
+where
+    T: Send + 'static,
+    F: Fn(Config, Progress) -> Fut,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+{
+    let bars = MultiProgress::new();
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut set = JoinSet::new();
+    for (i, target) in targets.iter().enumerate() {
+        let progress = Progress {
+            bars: bars.clone(),
+            label: target.name().to_string(),
+        };
+        let fut = target.config(filename).map(|config| job(config, progress));
+        let semaphore = semaphore.clone();
+        set.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (i, run_job(fut).await)
+        });
+    }
+
+    let mut results = vec![];
+    while let Some(result) = set.join_next().await {
+        results.push(result?);
+    }
+
+    Ok(results
+        .into_iter()
+        .sorted_by_key(|(i, _)| *i)
+        .map(|(_, r)| r)
+        .collect())
+}
+
+async fn run_job<T>(fut: Result<impl Future<Output = Result<T>>>) -> Result<T> {
+    fut?.await
+}
+
+fn failed(errors: Vec<String>) -> Result<()> {
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(eyre!("error fetching {} artifact(s)", errors.len())
+            .section(errors.join("\n").header("Errors:"))),
+    }
 }
 
 fn read(filename: &Path) -> Result<Document> {
@@ -50,37 +414,343 @@ fn write(filename: &Path, doc: Document) -> Result<()> {
     Ok(())
 }
 
-async fn fetch_checksum(filename: &Path, platform: &str) -> Result<String> {
-    let (os, arch) = split_platform_name(platform);
-    let config = Config::from_chim_file(filename, &os, &arch)?;
+async fn fetch_checksum(config: &Config, algorithm: &str, progress: Progress) -> Result<LockEntry> {
     let tmpdir = tempdir()?;
     let tmpfile = tmpdir.path().join("archive");
 
-    info!("fetching checksum for {}", config.url);
-    fetchers::new(&config).fetch(&config.url, &tmpfile).await?;
+    debug!("fetching checksum for {}", config.url);
+    let fetched = fetchers::new(config)
+        .hash_with(algorithm)
+        .with_progress(progress)
+        .fetch(&config.url, &tmpfile)
+        .await?;
 
-    let checksum = format!("sha256:{}", get_checksum::<Sha256>(&tmpfile)?);
-    info!("checksum: {}", checksum);
+    let checksum = match fetched.checksum {
+        Some(checksum) => checksum,
+        None => format!("{algorithm}:{}", get_checksum(&tmpfile, algorithm)?),
+    };
+    debug!("checksum for {}: {}", config.url, checksum);
 
-    Ok(checksum)
+    Ok(LockEntry {
+        url: config.redacted_url.clone(),
+        checksum,
+        size: Some(fs::metadata(&tmpfile)?.len()),
+    })
 }
 
 #[cfg(test)]
-#[cfg(feature = "test-e2e")]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
-    use std::fs::File;
-    use std::io::Write;
-    use std::path::PathBuf;
-    use tempfile::{tempdir, TempDir};
+    use test_case::test_case;
+
+    const ARTIFACT_CHECKSUM: &str =
+        "sha256:3b7f57b2e528faffbccccdb9f49a87b730a71b2f6aafa4b889541674d36f3e63";
 
+    #[tokio::test]
+    async fn test_check_platform() {
+        let dir = tempdir().unwrap();
+        fs::copy(
+            "test/fixtures/signature/artifact",
+            dir.path().join("artifact"),
+        )
+        .unwrap();
+        let status = |name: &str, body: &str| {
+            let chim_path = dir.path().join(name);
+            fs::write(&chim_path, body).unwrap();
+            let config = Config::from_chim_file(&chim_path, "linux", "x86_64", None).unwrap();
+            let progress = Progress {
+                bars: MultiProgress::new(),
+                label: name.to_string(),
+            };
+            async move { check_platform(&config, progress).await.unwrap() }
+        };
+
+        let ok =
+            format!("url = 'local:artifact'\npath = 'artifact'\nchecksum = '{ARTIFACT_CHECKSUM}'");
+        assert_eq!(status("ok", &ok).await, Status::Ok);
+        let mismatch = format!(
+            "url = 'local:artifact'\npath = 'artifact'\nchecksum = 'sha256:{}'",
+            "0".repeat(64)
+        );
+        assert_eq!(
+            status("mismatch", &mismatch).await,
+            Status::Mismatch {
+                expected: format!("sha256:{}", "0".repeat(64)),
+                actual: ARTIFACT_CHECKSUM.to_string(),
+            }
+        );
+        // missing checksums don't fetch the artifact, which doesn't exist here
+        let missing = "url = 'local:nonexistent'\npath = 'nonexistent'";
+        assert_eq!(status("missing", missing).await, Status::Missing);
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let dir = tempdir().unwrap();
+        fs::copy(
+            "test/fixtures/signature/artifact",
+            dir.path().join("artifact"),
+        )
+        .unwrap();
+        let chim_path = dir.path().join("tool");
+        let body =
+            format!("url = 'local:artifact'\npath = 'artifact'\nchecksum = '{ARTIFACT_CHECKSUM}'");
+        fs::write(&chim_path, &body).unwrap();
+        let targets = vec![Target { table: None }];
+        check(&chim_path, targets.clone(), 1).await.unwrap();
+        assert_eq!(fs::read_to_string(&chim_path).unwrap(), body);
+
+        fs::write(&chim_path, "url = 'local:artifact'\npath = 'artifact'").unwrap();
+        assert!(check(&chim_path, targets, 1).await.is_err());
+    }
+
+    fn table(toml: &str) -> Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn names(targets: Result<Vec<Target>>) -> Vec<String> {
+        targets
+            .unwrap()
+            .iter()
+            .map(|t| t.name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_get_targets() {
+        let chim = table(
+            r#"
+            url = "https://example.com/tool.tar.gz"
+            [env]
+            TOOL_HOME = "{install_dir}"
+            [linux-x86_64]
+            url = "https://example.com/tool-linux.tar.gz"
+            [linux-aarch64]
+            path = "bin/tool"
+            [macos]
+            url = "https://example.com/tool-macos.tar.gz"
+            "#,
+        );
+        let targets = |platforms: &[&str]| {
+            let platforms = platforms.iter().map(|p| p.to_string()).collect_vec();
+            get_targets(&chim, &platforms)
+        };
+        assert_eq!(names(targets(&[])), ["default", "linux-x86_64", "macos"]);
+        assert_eq!(names(targets(&["linux-x64"])), ["linux-x86_64"]);
+        assert_eq!(names(targets(&["darwin-arm64", "macos-x64"])), ["macos"]);
+        // platforms without a table of their own use the top-level url
+        assert_eq!(names(targets(&["linux-arm64", "windows-x64"])), ["default"]);
+        assert!(targets(&["linux-x64-foo"]).is_err());
+    }
+
+    #[test]
+    fn test_get_targets_templated() {
+        let chim = table(
+            r#"
+            url = "https://example.com/tool-{os}-{arch}.tar.gz"
+            [linux-x86_64]
+            [linux-aarch64]
+            url = "https://example.com/tool-linux-arm.tar.gz"
+            [macos]
+            path = "bin/tool"
+            [macos-aarch64]
+            fallback = ["macos-x86_64"]
+            "#,
+        );
+        let targets = |platforms: &[&str]| {
+            let platforms = platforms.iter().map(|p| p.to_string()).collect_vec();
+            get_targets(&chim, &platforms)
+        };
+        assert_eq!(names(targets(&[])), ["linux-aarch64", "linux-x86_64"]);
+        assert_eq!(
+            names(targets(&["linux-x64", "windows-x64"])),
+            ["linux-x86_64", "windows-x86_64"]
+        );
+
+        let chim = table(r#"url = "https://example.com/tool-{os}-{arch}.tar.gz""#);
+        assert!(get_targets(&chim, &[]).is_err());
+        assert_eq!(
+            names(get_targets(&chim, &["linux-x64".to_string()])),
+            ["linux-x86_64"]
+        );
+    }
+
+    #[test]
+    fn test_get_targets_without_url() {
+        let chim = table(
+            r#"
+            [linux-x86_64]
+            url = "https://example.com/tool-linux.tar.gz"
+            "#,
+        );
+        assert_eq!(names(get_targets(&chim, &[])), ["linux-x86_64"]);
+        assert!(get_targets(&chim, &["macos-x64".to_string()]).is_err());
+    }
+
+    #[test_case("linux-x86_64", "linux-x64", true ; "concrete")]
+    #[test_case("macos", "darwin-arm64", true ; "os only")]
+    #[test_case("*-aarch64", "linux-arm64", true ; "arch only")]
+    #[test_case("default", "windows-x64", true ; "default")]
+    #[test_case("linux-x86_64", "linux-arm64", false ; "other arch")]
+    #[test_case("linux", "linux", false ; "platform not concrete")]
+    fn test_matches_key(key: &str, platform: &str, expected: bool) {
+        let key = PlatformKey::parse(key).unwrap();
+        let platform = PlatformKey::parse(platform).unwrap();
+        assert_eq!(matches_key(&key, &platform), expected);
+    }
+
+    #[test_case("darwin-arm64", "macos-aarch64")]
+    #[test_case("linux-x64-musl", "linux-x86_64-musl")]
+    #[test_case("Windows", "windows")]
+    #[test_case("*", "default")]
+    fn test_normalize_platform(platform: &str, expected: &str) {
+        assert_eq!(normalize_platform(platform).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_set_checksum() {
+        let mut doc = r#"
+# keep me
+url = "https://example.com/tool.tar.gz"
+
+[darwin-arm64]
+url = "https://example.com/tool-macos.tar.gz"
+"#
+        .parse::<Document>()
+        .unwrap();
+        Target { table: None }.set_checksum(&mut doc, "sha256:abc".to_string());
+        let macos = Target {
+            table: Some("macos-aarch64".to_string()),
+        };
+        macos.set_checksum(&mut doc, "sha256:def".to_string());
+        let linux = Target {
+            table: Some("linux-x86_64".to_string()),
+        };
+        linux.set_checksum(&mut doc, "sha256:ghi".to_string());
+        assert_eq!(
+            doc.to_string(),
+            r#"
+# keep me
+url = "https://example.com/tool.tar.gz"
+checksum = "sha256:abc"
+
+[darwin-arm64]
+url = "https://example.com/tool-macos.tar.gz"
+checksum = "sha256:def"
+
+[linux-x86_64]
+checksum = "sha256:ghi"
+"#
+        );
+
+        let chim = table(&doc.to_string());
+        assert!(Target { table: None }.has_checksum(&chim));
+        assert!(!macos.has_checksum(&chim));
+    }
+
+    fn args(chim_file: &Path, platforms: &[&str], missing_only: bool) -> Args {
+        Args {
+            chim_file: chim_file.to_path_buf(),
+            algorithm: "sha256".to_string(),
+            lock: true,
+            check: false,
+            platforms: platforms.iter().map(|p| p.to_string()).collect(),
+            missing_only,
+            jobs: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lock() {
+        let dir = tempdir().unwrap();
+        fs::copy(
+            "test/fixtures/signature/artifact",
+            dir.path().join("artifact"),
+        )
+        .unwrap();
+        let chim_path = dir.path().join("tool");
+        fs::write(
+            &chim_path,
+            r#"
+            [linux-x64]
+            url = "local:artifact"
+            path = "artifact"
+            [macos-arm64]
+            url = "local:artifact"
+            path = "artifact"
+            "#,
+        )
+        .unwrap();
+        let lockfile_path = Lockfile::path(&chim_path);
+        let old = LockEntry {
+            url: "local:old".to_string(),
+            checksum: "sha256:old".to_string(),
+            size: None,
+        };
+        let mut lockfile = Lockfile::default();
+        lockfile.set(
+            "tool",
+            [
+                ("macos-aarch64".to_string(), old.clone()),
+                ("windows-x86_64".to_string(), old.clone()),
+            ]
+            .into(),
+        );
+        lockfile.save(&lockfile_path).unwrap();
+        let locked = |args: Args| async move {
+            let (chim, _) = chim_file::load(&args.chim_file).unwrap();
+            let targets = get_targets(&chim, &args.platforms).unwrap();
+            lock(&args.chim_file, targets, &args).await.unwrap();
+            Lockfile::load(&Lockfile::path(&args.chim_file))
+                .unwrap()
+                .entries("tool")
+                .into_iter()
+                .map(|(platform, entry)| (platform, entry.checksum))
+                .collect_vec()
+        };
+        let new = ARTIFACT_CHECKSUM.to_string();
+
+        // --missing-only and --platform keep the other entries
+        assert_eq!(
+            locked(args(&chim_path, &[], true)).await,
+            [
+                ("linux-x86_64".to_string(), new.clone()),
+                ("macos-aarch64".to_string(), old.checksum.clone()),
+                ("windows-x86_64".to_string(), old.checksum.clone()),
+            ]
+        );
+        assert_eq!(
+            locked(args(&chim_path, &["darwin-arm64"], false)).await,
+            [
+                ("linux-x86_64".to_string(), new.clone()),
+                ("macos-aarch64".to_string(), new.clone()),
+                ("windows-x86_64".to_string(), old.checksum.clone()),
+            ]
+        );
+        // a full run replaces everything
+        assert_eq!(
+            locked(args(&chim_path, &[], false)).await,
+            [
+                ("linux-x86_64".to_string(), new.clone()),
+                ("macos-aarch64".to_string(), new.clone()),
+            ]
+        );
+    }
+
+    #[cfg(feature = "test-e2e")]
     #[tokio::test]
     async fn test_checksums() {
         let dir = tempdir().unwrap();
         let chim_path = create_chim(&dir);
         run(Args {
             chim_file: chim_path.clone(),
+            algorithm: "sha256".to_string(),
+            lock: false,
+            check: false,
+            platforms: vec![],
+            missing_only: false,
+            jobs: 4,
         })
         .await
         .unwrap();
@@ -102,11 +772,12 @@ checksum = "sha256:9c0abfe32291dd5bed717463cb3590004289f03ab66011e383daa0fcec674
         );
     }
 
-    fn create_chim(dir: &TempDir) -> PathBuf {
+    #[cfg(feature = "test-e2e")]
+    fn create_chim(dir: &tempfile::TempDir) -> PathBuf {
         let filename = dir.path().join("node");
-        let mut file = File::create(&filename).unwrap();
-        file.write_all(
-            br#"#!/usr/bin/env chim
+        fs::write(
+            &filename,
+            r#"#!/usr/bin/env chim
 execvp = false
 [macos-arm64]
 url = 'https://nodejs.org/dist/v18.7.0/node-v18.7.0-darwin-arm64.tar.gz'
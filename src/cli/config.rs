@@ -0,0 +1,24 @@
+use crate::chim_file;
+use color_eyre::Result;
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+#[clap(about = "Prints the settings of a chim after extends and where each one came from")]
+pub struct Args {
+    #[clap(help = "The path to the chim file")]
+    chim_file: PathBuf,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let (table, sources) = chim_file::load(&args.chim_file)?;
+    let lines = chim_file::flatten(&table)
+        .into_iter()
+        .map(|(key, value)| (format!("{key} = {value}"), &sources[&key]))
+        .collect::<Vec<_>>();
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    for (line, source) in lines {
+        println!("{line:<width$}  # {}", source.display());
+    }
+
+    Ok(())
+}
@@ -1,7 +1,10 @@
 mod checksums;
+mod config;
+mod policy;
 mod run;
 mod version;
 
+use crate::schema;
 use clap::{Parser, Subcommand};
 use color_eyre::Result;
 use std::path::Path;
@@ -11,27 +14,50 @@ use std::path::Path;
 struct Cli {
     #[clap(subcommand)]
     command: Option<Commands>,
+
+    /// Warn about unknown keys in chims instead of erroring, same as CHIM_LENIENT=1
+    #[clap(long, global = true)]
+    lenient: bool,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
     Checksums(checksums::Args),
+    Config(config::Args),
+    Policy(policy::Args),
 }
 
-pub async fn parse(args: Vec<String>) -> Result<()> {
-    if let Some(arg) = args.get(1) {
-        match arg.as_ref() {
-            arg if arg_is_path(arg) => return run::run(args).await,
-            "-v" | "version" => return version::run(),
-            _ => {}
+pub async fn parse(mut args: Vec<String>) -> Result<()> {
+    let flags = args
+        .iter()
+        .skip(1)
+        .take_while(|a| RUN_FLAGS.contains(&a.as_str()))
+        .count();
+    if args.get(flags + 1).is_some_and(|a| arg_is_path(a)) {
+        let flags = args.drain(1..flags + 1).collect::<Vec<_>>();
+        if flags.iter().any(|f| f == "--lenient") {
+            schema::set_lenient();
         }
-    };
+        return run::run(args, flags.iter().any(|f| f == "--locked")).await;
+    }
+    if let Some("-v" | "version") = args.get(1).map(String::as_str) {
+        return version::run();
+    }
 
-    match Cli::parse_from(args).command.unwrap() {
+    let cli = Cli::parse_from(args);
+    if cli.lenient {
+        schema::set_lenient();
+    }
+    match cli.command.unwrap() {
         Commands::Checksums(args) => checksums::run(args).await,
+        Commands::Config(args) => config::run(args),
+        Commands::Policy(args) => policy::run(args),
     }
 }
 
+/// flags accepted before the chim when running one, e.g.: `chim --locked ./node -v`
+const RUN_FLAGS: [&str; 2] = ["--locked", "--lenient"];
+
 /// detects if we should attempt to run a chim or not
 /// this should be true if called from a shebang or .bat file
 fn arg_is_path(arg: &str) -> bool {
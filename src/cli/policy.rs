@@ -0,0 +1,17 @@
+use crate::policy::{self, Policy};
+use color_eyre::Result;
+
+#[derive(Debug, clap::Args)]
+#[clap(about = "Prints the effective security policy")]
+pub struct Args {}
+
+pub fn run(_args: Args) -> Result<()> {
+    let policy = Policy::load()?;
+    match policy::path() {
+        Some(path) if path.exists() => println!("# policy file: {}", path.display()),
+        _ => println!("# policy file: none"),
+    }
+    print!("{}", toml::to_string(&policy)?);
+
+    Ok(())
+}
@@ -1,30 +1,57 @@
 use crate::app::App;
 use crate::config::{Config, Fetcher};
-use color_eyre::eyre::Result;
+use crate::platform;
+use color_eyre::eyre::{eyre, Result};
 use color_eyre::Section;
-use std::env::consts::{ARCH, OS};
 use std::path::Path;
 
-pub async fn run(args: Vec<String>) -> Result<()> {
+pub async fn run(args: Vec<String>, locked: bool) -> Result<()> {
     let filename = Path::new(&args[1]);
-    let config = Config::from_chim_file(filename, OS, ARCH)
+    let (os, arch, libc) = platform::current()?;
+    let mut config = Config::from_chim_file(filename, &os, &arch, libc.as_deref())
         .with_section(|| format!("Chim: {}", filename.to_string_lossy()))?;
     debug!("config: {:#?}", config);
 
-    let app = App::new(&config)?;
-    if !config.bin_exists() {
-        match config.fetcher {
-            Fetcher::Local => {}
-            _ => {
+    let locked = locked || config.require_lock;
+    if locked && !config.locked && !matches!(config.fetcher, Fetcher::Local) {
+        return Err(eyre!(
+            "no chim.lock entry for {}-{} matches {}",
+            os,
+            arch,
+            config.url
+        )
+        .with_section(|| format!("Chim: {}", filename.to_string_lossy()))
+        .suggestion(format!(
+            "run `chim checksums --lock {}` to update chim.lock",
+            filename.display()
+        )));
+    }
+
+    let installed = match config.fetcher {
+        Fetcher::Local => true,
+        _ => config.bin_exists() && App::new(&config)?.verify_install()?,
+    };
+    if !installed {
+        let app = App::new(&config)?;
+        config.bin_path = match app.can_stream() {
+            true => app.fetch_and_extract().await?,
+            false => {
                 let tmpdir = tempfile::tempdir()?;
                 let archive = tmpdir.path().join("archive");
-                app.fetch(&archive).await?;
-                app.validate(&archive)?;
-                app.extract(&archive)?;
+                let checksum = app.get_checksum().await?;
+                let signature = app.get_signature().await?;
+                let fetched = app.fetch(&archive, checksum.as_deref()).await?;
+                app.validate(
+                    &archive,
+                    &fetched,
+                    checksum.as_deref(),
+                    signature.as_deref(),
+                )?;
+                app.extract(&archive, &fetched, checksum)?
             }
-        }
+        };
     }
-    app.exec(args)
+    App::new(&config)?.exec(args)
 }
 
 #[cfg(test)]
@@ -44,17 +71,23 @@ mod tests {
         }
         let dir = tempdir().unwrap();
         let chim_path = create_chim(&dir);
-        run(args_to_str(vec!["node", chim_path.to_str().unwrap(), "-v"]))
-            .await
-            .unwrap();
+        run(
+            args_to_str(vec!["node", chim_path.to_str().unwrap(), "-v"]),
+            false,
+        )
+        .await
+        .unwrap();
     }
 
     #[tokio::test]
     async fn test_jq() {
         let chim_path = Path::new("example/jq");
-        run(args_to_str(vec!["jq", chim_path.to_str().unwrap(), "-V"]))
-            .await
-            .unwrap();
+        run(
+            args_to_str(vec!["jq", chim_path.to_str().unwrap(), "-V"]),
+            false,
+        )
+        .await
+        .unwrap();
     }
 
     fn create_chim(dir: &TempDir) -> PathBuf {
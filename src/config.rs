@@ -1,13 +1,20 @@
+use crate::cache;
 use crate::chim_file::ChimFile;
-use crate::chim_file::Platform;
+use crate::chim_file::{Platform, ResolvedPlatform};
 use crate::env;
-use color_eyre::eyre::{eyre, Report, Result};
+use crate::lockfile::{LockEntry, Lockfile, DEFAULT_PLATFORM};
+use crate::policy::Policy;
+use crate::signature;
+use crate::template::Vars;
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
 use color_eyre::Section;
 use reqwest::Url;
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Fetcher {
     Local,
     Http,
@@ -18,16 +25,28 @@ pub enum Fetcher {
     // Git,
 }
 
-#[derive(Debug)]
+/// how installed files are checked for tampering before they are executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verify {
+    Off,
+    /// compare the binary's size and mtime, rehashing it only if they changed
+    Quick,
+    /// rehash every file on every run
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Archive {
     TarGz,
     TarXz,
     TarBz2,
+    TarZst,
     Tar,
     Zip,
     Gz,
     Xz,
     Bz2,
+    Zst,
     None,
 }
 
@@ -38,14 +57,61 @@ pub struct Config {
 
     pub fetcher: Fetcher,
     pub archive: Archive,
+    /// formats of archives nested inside `archive`, from the outermost layer in
+    pub nested_archives: Vec<Archive>,
+    /// glob selecting the file to unpack from each nested layer
+    pub inner: Option<String>,
+    /// true if archive was not set explicitly and should be detected from the downloaded file
+    pub detect_archive: bool,
+    /// glob patterns of archive entries to extract, everything is extracted if empty
+    pub extract: Vec<String>,
+    /// number of leading path components to strip from archive entries
+    pub strip_components: usize,
+    /// gnu or musl on linux
+    pub libc: Option<String>,
     pub url: String,
+    /// `url` with credentials from environment variables left as `${VAR}`, used in chim.lock
+    pub redacted_url: String,
     pub checksum: Option<String>,
+    /// expected size of the artifact from chim.lock
+    pub size: Option<u64>,
+    /// true if the checksum and size came from a matching entry in chim.lock
+    pub locked: bool,
+    /// refuse to run without a chim.lock entry, set with CHIM_LOCKED
+    pub require_lock: bool,
+    /// url of a checksum manifest like SHASUMS256.txt, only set if `checksum` is not
+    pub checksum_url: Option<String>,
+    /// pins the checksum manifest itself
+    pub checksum_url_checksum: Option<String>,
+    /// url of a detached signature for the artifact
+    pub signature_url: Option<String>,
+    /// trusted minisign, cosign or OpenPGP public key used to verify `signature_url`
+    pub public_key: Option<String>,
+    /// checksum of the binary itself after extraction
+    pub bin_checksum: Option<String>,
+    pub verify: Verify,
+    /// seconds between full verifications in quick mode, set with CHIM_VERIFY_INTERVAL
+    pub verify_interval: Option<u64>,
     pub execvp: bool,
-    pub paranoid: bool,
+    pub policy: Policy,
     pub quiet: bool,
 
+    /// path to the binary, may be a glob pattern or None to search archives for `name`
+    pub path: Option<String>,
+    /// the file a single compressed or uncompressed download is written to when `path` isn't set
+    pub default_path: Option<String>,
     pub bin_path: PathBuf,
     pub cache_path: PathBuf,
+    /// environment variables set for the binary
+    pub env: BTreeMap<String, String>,
+    /// directories added to the front of PATH for the binary
+    pub path_prepend: Vec<PathBuf>,
+    /// arguments passed before the user's
+    pub args: Vec<String>,
+    /// arguments passed after the user's
+    pub args_append: Vec<String>,
+    /// directory the binary runs in, None keeps the invoking directory
+    pub cwd: Option<PathBuf>,
 
     // s3
     pub aws_profile: Option<String>,
@@ -63,41 +129,128 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn from_chim_file(chim_path: &Path, os: &str, arch: &str) -> Result<Config> {
+    pub fn from_chim_file(
+        chim_path: &Path,
+        os: &str,
+        arch: &str,
+        libc: Option<&str>,
+    ) -> Result<Config> {
         let chim_file = ChimFile::from_file(chim_path)?;
         let name = chim_path.file_name().unwrap().to_string_lossy().to_string();
-        let chim_dir = chim_path.parent().unwrap();
-
-        let default_platform = Platform::default();
-        let platform = chim_file
-            .platforms
-            .get(&format!("{}-{}", os, arch))
-            .or_else(|| match (os, arch) {
-                ("macos", "aarch64") => chim_file.platforms.get("macos-x86_64"),
-                _ => None,
-            })
-            .unwrap_or(&default_platform);
-
-        let url = get_url(&chim_file, platform);
-        let archive = get_archive(&chim_file, platform, &url)?;
-        let fetcher = get_fetcher(&url)?;
-        let path = get_path(&chim_file, platform, &url, &archive)
-            .ok_or_else(|| show_no_url_or_path_error(&fetcher, os, arch))?;
+        let chim_dir = chim_dir(chim_path);
+
+        let resolved = chim_file.resolve_platform(os, arch, libc)?;
+        let platform = &resolved.platform;
+        let vars = Vars::new(
+            &chim_file,
+            platform,
+            &resolved.os,
+            &resolved.arch,
+            resolved.libc.as_deref(),
+        );
 
-        let cache_path = get_cache_path(&url)?;
-        let bin_path = get_bin_path(&fetcher, chim_dir, &cache_path, &path);
+        let raw_url = get_url(&chim_file, platform);
+        let url = vars.render(&raw_url)?;
+        let archives = get_archives(&chim_file, platform, &url)?;
+        let detect_archive = platform.archive.is_none() && chim_file.archive.is_none();
+        let inner = platform.inner.clone().or_else(|| chim_file.inner.clone());
+        let fetcher = get_fetcher(&url)?;
+        let filename = match (archives.as_slice(), &inner) {
+            ([_], _) => get_filename_from_url(&url)?,
+            (_, Some(inner)) if !cache::is_glob(inner) => inner.clone(),
+            (_, _) => name.clone(),
+        };
+        let default_path = get_default_path(&url, archives.last().unwrap(), &filename);
+        // a sniffed download may turn out to be an archive, its binary is searched for instead
+        let path = vars.render_opt(get_path(&chim_file, platform).or_else(
+            || match detect_archive {
+                true => None,
+                false => default_path.clone(),
+            },
+        ))?;
+        if path.is_none() && matches!(fetcher, Fetcher::Local) {
+            return Err(show_no_url_or_path_error(&fetcher, os, arch));
+        }
 
-        Ok(Config {
+        let redacted_url = vars.render(env::redact(&raw_url, &chim_file.redacted))?;
+        let lock_entry = get_lock_entry(chim_path, &name, &resolved, &redacted_url)?;
+        let mut checksum = get_checksum(&chim_file, platform);
+        let mut checksum_url = vars.render_opt(get_checksum_url(&chim_file, platform))?;
+        if let (None, Some(lock_entry)) = (&checksum, &lock_entry) {
+            checksum = Some(normalize_checksum(&lock_entry.checksum));
+            checksum_url = None;
+        }
+        let extract = get_extract(&chim_file, platform);
+        let strip_components = get_strip_components(&chim_file, platform);
+        let cache_key = get_cache_key(
+            &redacted_url,
+            checksum.as_deref(),
+            &extract,
+            strip_components,
+            inner.as_deref(),
+        );
+        let cache_path = get_cache_root()?.join(str_to_sha256(&cache_key));
+        let bin_path = match &path {
+            Some(path) if !cache::is_glob(path) => {
+                get_bin_path(&fetcher, chim_dir, &cache_path, path)
+            }
+            pattern => get_cached_bin_path(&cache_path, pattern.as_deref(), &name)?,
+        };
+        let abs_chim_dir = std::path::absolute(chim_dir)?;
+        let install_dir = match fetcher {
+            Fetcher::Local => abs_chim_dir.clone(),
+            _ => cache_path.clone(),
+        };
+        let dirs = Dirs {
+            chim_dir: &abs_chim_dir,
+            install_dir: &install_dir,
+        };
+        let env = get_env(&chim_file, platform, &vars, &dirs)?;
+        let path_prepend = get_path_prepend(&chim_file, platform, &vars, &install_dir)?;
+        let args = get_args(chim_file.args.clone(), platform.args.clone(), &vars, &dirs)?;
+        let args_append = get_args(
+            chim_file.args_append.clone(),
+            platform.args_append.clone(),
+            &vars,
+            &dirs,
+        )?;
+        let policy = Policy::load()?;
+
+        let config = Config {
             chim_path: chim_path.to_path_buf(),
             name,
             fetcher,
-            archive,
+            archive: archives[0],
+            nested_archives: archives[1..].to_vec(),
+            inner,
+            detect_archive,
+            extract,
+            strip_components,
+            libc: resolved.libc.clone(),
             url,
-            checksum: get_checksum(&chim_file, platform),
+            redacted_url,
+            checksum,
+            size: lock_entry.as_ref().and_then(|e| e.size),
+            locked: lock_entry.is_some(),
+            require_lock: get_require_lock(),
+            checksum_url,
+            checksum_url_checksum: get_checksum_url_checksum(&chim_file, platform),
+            signature_url: vars.render_opt(get_signature_url(&chim_file, platform))?,
+            public_key: get_public_key(&chim_file, platform, chim_dir)?,
+            bin_checksum: get_bin_checksum(&chim_file, platform),
+            verify: get_verify()?,
+            verify_interval: get_verify_interval()?,
+            path,
+            default_path,
             bin_path,
             cache_path,
+            env,
+            path_prepend,
+            args,
+            args_append,
+            cwd: get_cwd(&chim_file, platform, &vars, &abs_chim_dir)?,
             execvp: get_execvp(&chim_file, platform),
-            paranoid: get_paranoid(),
+            policy,
             quiet: get_quiet(&chim_file),
 
             // s3
@@ -108,16 +261,19 @@ impl Config {
             aws_region: get_aws_region(&chim_file, platform),
 
             // hooks
-            pre_fetch: chim_file.pre_fetch,
-            pre_extract: chim_file.pre_extract,
-            pre_execute: chim_file.pre_execute,
-            post_execute: chim_file.post_execute,
+            pre_fetch: vars.render_opt(chim_file.pre_fetch)?,
+            pre_extract: vars.render_opt(chim_file.pre_extract)?,
+            pre_execute: vars.render_opt(chim_file.pre_execute)?,
+            post_execute: vars.render_opt(chim_file.post_execute)?,
             //pre_execute_interval: chim_file.pre_execute_interval,
-        })
+        };
+        config.policy.check(&config)?;
+
+        Ok(config)
     }
 
     pub fn bin_exists(&self) -> bool {
-        Path::exists(self.bin_path.as_path())
+        self.bin_path.is_file()
     }
 }
 
@@ -137,8 +293,40 @@ fn get_url(chim_file: &ChimFile, platform: &Platform) -> String {
         .unwrap_or_else(|| String::from("local:"))
 }
 
-fn get_cache_path(url: &str) -> Result<PathBuf> {
-    Ok(get_cache_root()?.join(str_to_sha256(url)))
+/// the cache is content-addressed by the expected checksum when there is one so a changed
+/// checksum gets a fresh install and mirrors of the same artifact share an entry
+/// options that change what is extracted are part of the key
+/// `url` must be redacted so rotating credentials keeps the cache
+fn get_cache_key(
+    url: &str,
+    checksum: Option<&str>,
+    extract: &[String],
+    strip_components: usize,
+    inner: Option<&str>,
+) -> String {
+    let mut key = match checksum {
+        Some(checksum) => checksum.to_lowercase(),
+        None => url.to_string(),
+    };
+    if !extract.is_empty() {
+        key.push_str(&format!("\nextract={}", extract.join(",")));
+    }
+    if strip_components > 0 {
+        key.push_str(&format!("\nstrip_components={strip_components}"));
+    }
+    if let Some(inner) = inner {
+        key.push_str(&format!("\ninner={inner}"));
+    }
+
+    key
+}
+
+/// the directory of the chim, `.` for a bare filename
+pub fn chim_dir(chim_path: &Path) -> &Path {
+    chim_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
 }
 
 fn get_bin_path(fetcher: &Fetcher, chim_dir: &Path, cache_path: &Path, path: &str) -> PathBuf {
@@ -155,47 +343,153 @@ fn get_bin_path(fetcher: &Fetcher, chim_dir: &Path, cache_path: &Path, path: &st
     .join(path)
 }
 
-fn get_path(
-    chim_file: &ChimFile,
-    platform: &Platform,
+/// the binary path recorded for this path glob or chim name when the archive was extracted
+/// falls back to the cache directory itself which never counts as an existing binary
+fn get_cached_bin_path(cache_path: &Path, pattern: Option<&str>, name: &str) -> Result<PathBuf> {
+    let entry = cache::Entry::load(cache_path)?.unwrap_or_default();
+    Ok(match entry.bin_paths.get(&cache::bin_key(pattern, name)) {
+        Some(bin_path) => cache_path.join(bin_path),
+        None => cache_path.to_path_buf(),
+    })
+}
+
+fn get_path(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
+    platform.path.clone().or_else(|| chim_file.path.clone())
+}
+
+/// where a single compressed or uncompressed file is written when `path` isn't set
+fn get_default_path(url: &str, archive: &Archive, filename: &str) -> Option<String> {
+    match archive {
+        Archive::Gz | Archive::Bz2 | Archive::Xz | Archive::Zst | Archive::None => {
+            if url == "local:" {
+                return None;
+            }
+            Some(
+                filename
+                    .trim_end_matches(".gz")
+                    .trim_end_matches(".xz")
+                    .trim_end_matches(".bz2")
+                    .trim_end_matches(".zst")
+                    .to_string(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// a checksum set on the platform takes precedence over a top-level checksum_url
+fn get_checksum(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
+    let checksum = match platform.checksum_url {
+        Some(_) => platform.checksum.clone(),
+        None => platform
+            .checksum
+            .clone()
+            .or_else(|| chim_file.checksum.clone()),
+    };
+    checksum.map(|checksum| normalize_checksum(&checksum))
+}
+
+fn get_checksum_url(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
+    match platform.checksum {
+        Some(_) => None,
+        None => platform
+            .checksum_url
+            .clone()
+            .or_else(|| match chim_file.checksum {
+                Some(_) => None,
+                None => chim_file.checksum_url.clone(),
+            }),
+    }
+}
+
+fn get_checksum_url_checksum(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
+    platform
+        .checksum_url_checksum
+        .clone()
+        .or_else(|| chim_file.checksum_url_checksum.clone())
+        .map(|checksum| normalize_checksum(&checksum))
+}
+
+/// the chim.lock entry for this platform, ignored if it was resolved from a different url
+/// entries may be keyed by the platform, the tables it matched or the top-level url
+fn get_lock_entry(
+    chim_path: &Path,
+    name: &str,
+    resolved: &ResolvedPlatform,
     url: &str,
-    archive: &Archive,
-) -> Option<String> {
+) -> Result<Option<LockEntry>> {
+    let lockfile = Lockfile::load(&Lockfile::path(chim_path))?;
+    let platform = format!("{}-{}", resolved.os, resolved.arch);
+    let entry = resolved
+        .libc
+        .iter()
+        .map(|libc| format!("{platform}-{libc}"))
+        .chain([platform.clone()])
+        .chain(resolved.keys.iter().cloned())
+        .chain([DEFAULT_PLATFORM.to_string()])
+        .find_map(|key| lockfile.get(name, &key));
+    Ok(match entry {
+        Some(entry) if entry.url == url => Some(entry.clone()),
+        Some(entry) => {
+            debug!("ignoring chim.lock entry for {}", entry.url);
+            None
+        }
+        None => None,
+    })
+}
+
+fn get_bin_checksum(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
     platform
-        .path
+        .bin_checksum
         .clone()
-        .or_else(|| chim_file.path.clone())
-        .or_else(|| match archive {
-            Archive::Gz | Archive::Bz2 | Archive::Xz | Archive::None => {
-                if url == "local:" {
-                    return None;
-                }
-                Some(
-                    get_filename_from_url(url)
-                        .unwrap()
-                        .trim_end_matches(".gz")
-                        .trim_end_matches(".xz")
-                        .trim_end_matches(".bz2")
-                        .to_string(),
-                )
-            }
-            _ => None,
-        })
+        .or_else(|| chim_file.bin_checksum.clone())
+        .map(|checksum| normalize_checksum(&checksum))
 }
 
-fn get_checksum(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
-    match platform
-        .checksum
+fn get_signature_url(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
+    platform
+        .signature_url
         .clone()
-        .or_else(|| chim_file.checksum.clone())
+        .or_else(|| chim_file.signature_url.clone())
+}
+
+/// the key may be set inline or as a path relative to the chim
+fn get_public_key(
+    chim_file: &ChimFile,
+    platform: &Platform,
+    chim_dir: &Path,
+) -> Result<Option<String>> {
+    let public_key = match platform
+        .public_key
+        .as_ref()
+        .or(chim_file.public_key.as_ref())
     {
-        Some(checksum) if !checksum.contains(':') => Some(format!("sha256:{}", checksum)),
-        Some(checksum) => Some(checksum),
-        None => None,
+        Some(public_key) => public_key,
+        None => return Ok(None),
+    };
+    if signature::is_inline_key(public_key) {
+        return Ok(Some(public_key.clone()));
+    }
+    let filename = chim_dir.join(public_key);
+    if !filename.is_file() {
+        return Err(eyre!("public key file not found: {}", filename.display())
+            .suggestion("set public_key to the key itself or a path relative to the chim"));
+    }
+
+    Ok(Some(fs::read_to_string(&filename).wrap_err_with(|| {
+        format!("error reading public key {}", filename.display())
+    })?))
+}
+
+/// checksums without an algorithm prefix are sha256
+fn normalize_checksum(checksum: &str) -> String {
+    match checksum.contains(':') {
+        true => checksum.to_string(),
+        false => format!("sha256:{}", checksum),
     }
 }
 
-fn get_fetcher(url: &str) -> Result<Fetcher> {
+pub fn get_fetcher(url: &str) -> Result<Fetcher> {
     match url.split(':').next().unwrap() {
         "local" => Ok(Fetcher::Local),
         "http" | "https" => Ok(Fetcher::Http),
@@ -207,37 +501,160 @@ fn get_fetcher(url: &str) -> Result<Fetcher> {
     }
 }
 
-fn get_archive(chim_file: &ChimFile, platform: &Platform, url: &str) -> Result<Archive> {
+/// the archive formats from the outermost layer in, always has at least one entry
+fn get_archives(chim_file: &ChimFile, platform: &Platform, url: &str) -> Result<Vec<Archive>> {
     let archive = platform
         .archive
         .clone()
         .or_else(|| chim_file.archive.clone());
     match &archive {
-        Some(archive) => match extension_to_archive(&format!(".{archive}")) {
-            Archive::None => Err(eyre!("unsupported archive: {}", archive)),
-            a => Ok(a),
-        },
+        Some(archive) => {
+            let formats = archive.formats();
+            if formats.is_empty() {
+                return Err(eyre!("archive must not be empty"));
+            }
+            formats
+                .iter()
+                .map(
+                    |archive| match extension_to_archive(&format!(".{archive}")) {
+                        Archive::None => Err(eyre!("unsupported archive: {}", archive)),
+                        a => Ok(a),
+                    },
+                )
+                .collect()
+        }
         None => {
             let filename = get_filename_from_url(url)?;
-            Ok(extension_to_archive(&filename))
+            Ok(vec![extension_to_archive(&filename)])
         }
     }
 }
 
-fn extension_to_archive(f: &str) -> Archive {
+pub fn extension_to_archive(f: &str) -> Archive {
     match f {
         f if f.ends_with(".tar.xz") || f.ends_with(".txz") => Archive::TarXz,
         f if f.ends_with(".tar.gz") || f.ends_with(".tgz") => Archive::TarGz,
         f if f.ends_with(".tar.bz2") || f.ends_with(".tbz2") => Archive::TarBz2,
+        f if f.ends_with(".tar.zst") || f.ends_with(".tzst") => Archive::TarZst,
         f if f.ends_with(".tar") => Archive::Tar,
         f if f.ends_with(".zip") => Archive::Zip,
         f if f.ends_with(".xz") => Archive::Xz,
         f if f.ends_with(".gz") => Archive::Gz,
         f if f.ends_with(".bz2") => Archive::Bz2,
+        f if f.ends_with(".zst") => Archive::Zst,
         _ => Archive::None,
     }
 }
 
+fn get_extract(chim_file: &ChimFile, platform: &Platform) -> Vec<String> {
+    platform
+        .extract
+        .clone()
+        .or_else(|| chim_file.extract.clone())
+        .unwrap_or_default()
+}
+
+/// directories substituted for `{chim_dir}` and `{install_dir}` in env and args
+struct Dirs<'a> {
+    chim_dir: &'a Path,
+    /// where the artifact was extracted, the chim's directory for local binaries
+    install_dir: &'a Path,
+}
+
+impl Dirs<'_> {
+    fn render(&self, vars: &Vars, s: &str) -> Result<String> {
+        Ok(vars
+            .render(s)?
+            .replace("{chim_dir}", &self.chim_dir.to_string_lossy())
+            .replace("{install_dir}", &self.install_dir.to_string_lossy()))
+    }
+}
+
+/// platform values override top-level ones with the same name
+fn get_env(
+    chim_file: &ChimFile,
+    platform: &Platform,
+    vars: &Vars,
+    dirs: &Dirs,
+) -> Result<BTreeMap<String, String>> {
+    chim_file
+        .env
+        .iter()
+        .chain(&platform.env)
+        .map(|(key, value)| Ok((key.clone(), dirs.render(vars, value)?)))
+        .collect()
+}
+
+fn get_args(
+    args: Option<Vec<String>>,
+    platform_args: Option<Vec<String>>,
+    vars: &Vars,
+    dirs: &Dirs,
+) -> Result<Vec<String>> {
+    platform_args
+        .or(args)
+        .unwrap_or_default()
+        .iter()
+        .map(|arg| dirs.render(vars, arg))
+        .collect()
+}
+
+fn get_path_prepend(
+    chim_file: &ChimFile,
+    platform: &Platform,
+    vars: &Vars,
+    install_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    platform
+        .path_prepend
+        .clone()
+        .or_else(|| chim_file.path_prepend.clone())
+        .unwrap_or_default()
+        .iter()
+        .map(|dir| Ok(install_dir.join(vars.render(dir)?)))
+        .collect()
+}
+
+fn get_cwd(
+    chim_file: &ChimFile,
+    platform: &Platform,
+    vars: &Vars,
+    chim_dir: &Path,
+) -> Result<Option<PathBuf>> {
+    let cwd = match platform.cwd.as_ref().or(chim_file.cwd.as_ref()) {
+        Some(cwd) => vars.render(cwd)?,
+        None => return Ok(None),
+    };
+    let cwd = match cwd.as_str() {
+        "chim_dir" => chim_dir.to_path_buf(),
+        "git_root" => find_git_root(chim_dir)?,
+        _ => chim_dir.join(cwd),
+    };
+
+    Ok(Some(cwd))
+}
+
+/// the closest directory above `dir` with a .git directory, or .git file for worktrees
+fn find_git_root(dir: &Path) -> Result<PathBuf> {
+    dir.ancestors()
+        .find(|d| d.join(".git").exists())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| {
+            eyre!(
+                "cwd is git_root but {} is not in a git repository",
+                dir.display()
+            )
+            .suggestion("set cwd to chim_dir or a path relative to the chim")
+        })
+}
+
+fn get_strip_components(chim_file: &ChimFile, platform: &Platform) -> usize {
+    platform
+        .strip_components
+        .or(chim_file.strip_components)
+        .unwrap_or(0)
+}
+
 fn get_execvp(chim_file: &ChimFile, platform: &Platform) -> bool {
     if env::var_is_false("CHIM_EXECVP") || chim_file.post_execute.is_some() {
         return false;
@@ -283,8 +700,28 @@ fn get_aws_region(chim_file: &ChimFile, platform: &Platform) -> Option<String> {
     }
 }
 
-fn get_paranoid() -> bool {
-    env::var_is_true("CHIM_PARANOID")
+fn get_require_lock() -> bool {
+    env::var_is_true("CHIM_LOCKED")
+}
+
+fn get_verify() -> Result<Verify> {
+    match std::env::var("CHIM_VERIFY").as_deref() {
+        Ok("off") => Ok(Verify::Off),
+        Ok("quick") | Err(_) => Ok(Verify::Quick),
+        Ok("full") => Ok(Verify::Full),
+        Ok(v) => Err(eyre!("invalid CHIM_VERIFY: {}", v).suggestion("use off, quick or full")),
+    }
+}
+
+fn get_verify_interval() -> Result<Option<u64>> {
+    match std::env::var("CHIM_VERIFY_INTERVAL") {
+        Ok(v) => v
+            .parse()
+            .map(Some)
+            .wrap_err_with(|| format!("invalid CHIM_VERIFY_INTERVAL: {}", v))
+            .suggestion("use a number of seconds"),
+        Err(_) => Ok(None),
+    }
 }
 
 fn get_cache_root() -> Result<PathBuf> {
@@ -307,10 +744,10 @@ fn show_no_url_or_path_error(fetcher: &Fetcher, os: &str, arch: &str) -> Report
         .suggestion(format!("add a {url_or_path} field to chim"))
 }
 
-fn get_filename_from_url(url: &str) -> Result<String> {
+pub fn get_filename_from_url(url: &str) -> Result<String> {
     Ok(Url::parse(url)?
         .path_segments()
-        .and_then(|segments| segments.last())
+        .and_then(|mut segments| segments.next_back())
         .and_then(|name| if name.is_empty() { None } else { Some(name) })
         .unwrap_or("download.file")
         .to_string())
@@ -324,15 +761,256 @@ fn get_quiet(chim_file: &ChimFile) -> bool {
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
+    use test_case::test_case;
 
     #[test]
     fn test_node_config() {
         let chim_path = Path::new("example/node");
-        let c = Config::from_chim_file(chim_path, "macos", "aarch64").unwrap();
+        let c = Config::from_chim_file(chim_path, "macos", "aarch64", None).unwrap();
 
         assert_eq!(
             c.url,
             "https://nodejs.org/dist/v18.7.0/node-v18.7.0-darwin-arm64.tar.gz"
         );
+        assert_eq!(c.archive, Archive::TarGz);
+        assert!(c.detect_archive);
+        assert_eq!(c.path.unwrap(), "node-v18.7.0-darwin-arm64/bin/node");
+
+        let c = Config::from_chim_file(chim_path, "linux", "x86_64", None).unwrap();
+        assert_eq!(
+            c.url,
+            "https://nodejs.org/dist/v18.7.0/node-v18.7.0-linux-x64.tar.xz"
+        );
+        assert_eq!(c.archive, Archive::TarXz);
+        assert_eq!(
+            c.checksum.unwrap(),
+            "sha256:8bc6a1b9deaed2586d726fc62d4bee9c1bfc5a30b96c1c4cff7edd15225a11a2"
+        );
+
+        let c = Config::from_chim_file(chim_path, "windows", "x86_64", None).unwrap();
+        assert_eq!(c.path.unwrap(), r"node-v18.7.0-win-x64\node.exe");
+    }
+
+    #[test]
+    fn test_platform_matching() {
+        let chim_path = Path::new("test/fixtures/platforms");
+        let c = Config::from_chim_file(chim_path, "linux", "x86_64", None).unwrap();
+        assert_eq!(c.url, "https://example.com/tool-linux-x86_64.tar.gz");
+        assert_eq!(c.path.unwrap(), "bin/tool");
+        assert_eq!(c.checksum.unwrap(), "sha256:abc");
+        assert_eq!(c.strip_components, 1);
+
+        let c = Config::from_chim_file(chim_path, "linux", "x86_64", Some("musl")).unwrap();
+        assert_eq!(c.url, "https://example.com/tool-linux-x86_64-musl.tar.gz");
+        assert_eq!(c.path.unwrap(), "bin/tool");
+        assert_eq!(c.checksum, None);
+
+        let c = Config::from_chim_file(chim_path, "linux", "aarch64", Some("musl")).unwrap();
+        assert_eq!(c.path.unwrap(), "bin/tool");
+        assert_eq!(c.checksum, None);
+        assert!(!c.execvp);
+
+        let c = Config::from_chim_file(chim_path, "windows", "aarch64", None).unwrap();
+        assert_eq!(c.url, "https://example.com/tool-windows-x86_64.tar.gz");
+
+        // no macos-aarch64 build so rosetta runs the universal one
+        let c = Config::from_chim_file(chim_path, "macos", "aarch64", None).unwrap();
+        assert_eq!(c.url, "https://example.com/tool-macos-universal.tar.gz");
+    }
+
+    #[test]
+    fn test_env() {
+        let chim_path = Path::new("test/fixtures/env");
+        let c = Config::from_chim_file(chim_path, "linux", "x86_64", None).unwrap();
+        let install_dir = c.cache_path.to_string_lossy();
+        assert_eq!(c.env["TOOL_HOME"], install_dir);
+        assert_eq!(c.env["TOOL_VERSION"], "1.0.0");
+        assert_eq!(c.path_prepend, vec![c.cache_path.join("bin")]);
+
+        let c = Config::from_chim_file(chim_path, "windows", "x86_64", None).unwrap();
+        assert_eq!(c.env["TOOL_VERSION"], "1.0.0-win");
+    }
+
+    #[test]
+    fn test_args() {
+        let chim_path = Path::new("test/fixtures/args");
+        let c = Config::from_chim_file(chim_path, "linux", "x86_64", None).unwrap();
+        let chim_dir = std::path::absolute("test/fixtures").unwrap();
+        let config = chim_dir.join("tool.toml");
+        assert_eq!(c.args, vec!["--config", config.to_str().unwrap()]);
+        assert_eq!(c.args_append, vec!["--verbose"]);
+
+        let c = Config::from_chim_file(chim_path, "windows", "x86_64", None).unwrap();
+        let config = format!("{}\\tool.toml", chim_dir.display());
+        assert_eq!(c.args, vec!["/config", config.as_str()]);
+        assert_eq!(c.args_append, vec!["--verbose"]);
+    }
+
+    #[test]
+    fn test_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let chim_dir = dir.path().join("repo/tools");
+        fs::create_dir_all(&chim_dir).unwrap();
+        fs::create_dir(dir.path().join("repo/.git")).unwrap();
+
+        let cwd = |cwd: &str| {
+            let chim_file = ChimFile {
+                cwd: Some(cwd.to_string()),
+                ..ChimFile::default()
+            };
+            let vars = Vars::new(&chim_file, &Platform::default(), "linux", "x86_64", None);
+            get_cwd(&chim_file, &Platform::default(), &vars, &chim_dir)
+        };
+        assert_eq!(cwd("chim_dir").unwrap().unwrap(), chim_dir);
+        assert_eq!(cwd("git_root").unwrap().unwrap(), dir.path().join("repo"));
+        assert_eq!(cwd("..").unwrap().unwrap(), chim_dir.join(".."));
+
+        fs::remove_dir(dir.path().join("repo/.git")).unwrap();
+        assert!(cwd("git_root").is_err());
+    }
+
+    #[test]
+    fn test_get_archive_without_extension() {
+        let chim_file = ChimFile::default();
+        let archives = get_archives(
+            &chim_file,
+            &Platform::default(),
+            "https://example.com/download?id=123",
+        )
+        .unwrap();
+        assert_eq!(archives, [Archive::None]);
+    }
+
+    #[test]
+    fn test_checksum_url_precedence() {
+        let chim_file = ChimFile {
+            checksum_url: Some("https://example.com/SHASUMS256.txt".to_string()),
+            ..ChimFile::default()
+        };
+        let platform = Platform::default();
+        assert_eq!(get_checksum(&chim_file, &platform), None);
+        assert!(get_checksum_url(&chim_file, &platform).is_some());
+
+        let platform = Platform {
+            checksum: Some("abc".to_string()),
+            ..Platform::default()
+        };
+        assert_eq!(get_checksum(&chim_file, &platform).unwrap(), "sha256:abc");
+        assert_eq!(get_checksum_url(&chim_file, &platform), None);
+
+        let chim_file = ChimFile {
+            checksum: Some("sha512:abc".to_string()),
+            ..ChimFile::default()
+        };
+        let platform = Platform {
+            checksum_url: Some("https://example.com/checksums.txt".to_string()),
+            ..Platform::default()
+        };
+        assert_eq!(get_checksum(&chim_file, &platform), None);
+        assert!(get_checksum_url(&chim_file, &platform).is_some());
+    }
+
+    #[test]
+    fn test_nested_config() {
+        let chim_path = Path::new("test/fixtures/nested");
+        let c = Config::from_chim_file(chim_path, "linux", "x86_64", None).unwrap();
+
+        assert_eq!(c.archive, Archive::Zip);
+        assert_eq!(c.nested_archives, [Archive::TarGz]);
+        assert_eq!(c.inner.unwrap(), "tool-linux.tar.gz");
+        assert!(!c.detect_archive);
+
+        let c = Config::from_chim_file(chim_path, "macos", "x86_64", None).unwrap();
+        assert_eq!(c.nested_archives, [Archive::Gz]);
+        assert_eq!(c.path.unwrap(), "tool-macos");
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let mirror1 = "https://example.com/jq";
+        let mirror2 = "https://mirror.example.com/jq";
+        assert_eq!(
+            get_cache_key(mirror1, Some("sha256:abc"), &[], 0, None),
+            get_cache_key(mirror2, Some("sha256:ABC"), &[], 0, None)
+        );
+        assert_ne!(
+            get_cache_key(mirror1, Some("sha256:abc"), &[], 0, None),
+            get_cache_key(mirror1, Some("sha256:def"), &[], 0, None)
+        );
+        assert_ne!(
+            get_cache_key(mirror1, None, &[], 0, None),
+            get_cache_key(mirror2, None, &[], 0, None)
+        );
+        assert_ne!(
+            get_cache_key(mirror1, Some("sha256:abc"), &[], 0, None),
+            get_cache_key(
+                mirror1,
+                Some("sha256:abc"),
+                &["*/bin/jq".to_string()],
+                1,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_cached_bin_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("abc");
+        cache::Entry {
+            bin_paths: [(cache::bin_key(None, "node"), "bin/node".to_string())].into(),
+            ..cache::Entry::default()
+        }
+        .save(&cache_path)
+        .unwrap();
+        assert_eq!(
+            get_cached_bin_path(&cache_path, None, "node").unwrap(),
+            cache_path.join("bin/node")
+        );
+        // npm shares node's cache directory but must not run node
+        assert_eq!(
+            get_cached_bin_path(&cache_path, None, "npm").unwrap(),
+            cache_path
+        );
+        assert_eq!(
+            get_cached_bin_path(&cache_path, Some("*/bin/npm"), "node").unwrap(),
+            cache_path
+        );
+    }
+
+    #[test_case("env", "." ; "bare filename")]
+    #[test_case("./env", "." ; "current directory")]
+    #[test_case("test/fixtures/env", "test/fixtures" ; "relative")]
+    #[test_case("/env", "/" ; "root")]
+    fn test_chim_dir(chim_path: &str, expected: &str) {
+        let dir = chim_dir(Path::new(chim_path));
+        assert_eq!(dir, Path::new(expected));
+        // resolving it must not fail for a chim run from its own directory
+        assert!(std::path::absolute(dir).is_ok());
+    }
+
+    #[test]
+    fn test_get_public_key() {
+        let chim_dir = Path::new("test/fixtures/signature");
+        let get = |public_key: &str| {
+            let chim_file = ChimFile {
+                public_key: Some(public_key.to_string()),
+                ..ChimFile::default()
+            };
+            get_public_key(&chim_file, &Platform::default(), chim_dir)
+        };
+        let key = "RWSl0lEwCOvV4FUFVVyWIKcG3GlmdZNeuV9OqkIXmYF7Gii0EXl2vOcl";
+
+        assert_eq!(get(key).unwrap().unwrap(), key);
+        assert!(get("minisign.pub").unwrap().unwrap().contains(key));
+        assert!(get("cosign.pub")
+            .unwrap()
+            .unwrap()
+            .contains("-----BEGIN PUBLIC KEY-----"));
+        let err = get("minisgn.pub").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "public key file not found: test/fixtures/signature/minisgn.pub"
+        );
     }
 }
@@ -1,5 +1,9 @@
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 
+/// parts of variable names that hold credentials
+const SECRET_NAMES: [&str; 6] = ["TOKEN", "SECRET", "PASSWORD", "PASSWD", "KEY", "CREDENTIAL"];
+
 pub fn var_is_true(key: &str) -> bool {
     match env::var(key) {
         Ok(v) => v == "true" || v == "1",
@@ -14,6 +18,97 @@ pub fn var_is_false(key: &str) -> bool {
     }
 }
 
+/// what `expand` found while replacing variables
+#[derive(Debug, Default)]
+pub struct Expansions {
+    /// variables without a value or default
+    pub unset: BTreeSet<String>,
+    /// expanded strings holding credentials, mapped to the same string with the `${VAR}`
+    /// of each credential left in place
+    pub redacted: HashMap<String, String>,
+}
+
+/// replaces `${VAR}` and `${VAR:-default}` with environment variables, `$${` is a literal `${`
+/// the default is used if the variable is unset or empty
+pub fn expand(s: &str, expansions: &mut Expansions) -> String {
+    let mut out = String::with_capacity(s.len());
+    // `out` with variables that look like credentials left in place
+    let mut redacted = String::with_capacity(s.len());
+    let mut has_secret = false;
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let (before, after) = rest.split_at(start);
+        if let Some(before) = before.strip_suffix('$') {
+            for out in [&mut out, &mut redacted] {
+                out.push_str(before);
+                out.push_str("${");
+            }
+            rest = &after[2..];
+            continue;
+        }
+        let Some(end) = after.find('}') else {
+            break;
+        };
+        out.push_str(before);
+        redacted.push_str(before);
+        let (var, inner) = (&after[..=end], &after[2..end]);
+        rest = &after[end + 1..];
+        let (name, default) = match inner.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (inner, None),
+        };
+        if !is_var_name(name) {
+            out.push_str(var);
+            redacted.push_str(var);
+            continue;
+        }
+        match (env::var(name).ok().filter(|v| !v.is_empty()), default) {
+            (Some(value), _) => {
+                out.push_str(&value);
+                match is_secret(name) {
+                    true => {
+                        has_secret = true;
+                        redacted.push_str(&format!("${{{name}}}"));
+                    }
+                    false => redacted.push_str(&value),
+                }
+            }
+            (None, Some(default)) => {
+                out.push_str(default);
+                redacted.push_str(default);
+            }
+            (None, None) => {
+                expansions.unset.insert(name.to_string());
+            }
+        }
+    }
+    out.push_str(rest);
+    redacted.push_str(rest);
+    if has_secret {
+        expansions.redacted.insert(out.clone(), redacted);
+    }
+
+    out
+}
+
+/// `s` with `${VAR}` in place of the credentials that were expanded into it
+pub fn redact<'a>(s: &'a str, redacted: &'a HashMap<String, String>) -> &'a str {
+    redacted.get(s).map_or(s, String::as_str)
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_NAMES.iter().any(|s| name.contains(s))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -53,6 +148,61 @@ mod tests {
         env::remove_var(&key);
     }
 
+    #[test]
+    fn test_expand() {
+        let key = format!("CHIM_TEST_VERSION_{}", rand::random::<u32>());
+        let token = format!("{key}_TOKEN");
+        env::set_var(&key, "1.0");
+        env::set_var(&token, "hunter2");
+        env::remove_var(format!("{key}_UNSET"));
+
+        let mut expansions = Expansions::default();
+        let url = expand(
+            &format!("https://${{{token}}}@example.com/${{{key}}}/${{{key}_UNSET:-tool}}?$${{x}}"),
+            &mut expansions,
+        );
+        assert_eq!(url, "https://hunter2@example.com/1.0/tool?${x}");
+        assert_eq!(
+            redact(&url, &expansions.redacted),
+            format!("https://${{{token}}}@example.com/1.0/tool?${{x}}")
+        );
+        assert_eq!(
+            redact("https://example.com", &expansions.redacted),
+            "https://example.com"
+        );
+        assert!(expansions.unset.is_empty());
+
+        assert_eq!(
+            expand(&format!("${{{key}_UNSET}} ${{not a var}}"), &mut expansions),
+            " ${not a var}"
+        );
+        assert_eq!(
+            expansions.unset.into_iter().collect::<Vec<_>>(),
+            [format!("{key}_UNSET")]
+        );
+        env::remove_var(&key);
+        env::remove_var(&token);
+    }
+
+    #[test]
+    fn test_redact_short_secret() {
+        let token = format!("CHIM_TEST_{}_TOKEN", rand::random::<u32>());
+        env::set_var(&token, "1");
+
+        // only the expanded value is redacted, not every "1" in the string
+        let mut expansions = Expansions::default();
+        let url = expand(
+            &format!("https://example.com/1.0/tool?token=${{{token}}}&v=1"),
+            &mut expansions,
+        );
+        assert_eq!(url, "https://example.com/1.0/tool?token=1&v=1");
+        assert_eq!(
+            redact(&url, &expansions.redacted),
+            format!("https://example.com/1.0/tool?token=${{{token}}}&v=1")
+        );
+        env::remove_var(&token);
+    }
+
     // #[quickcheck]
     fn get_key() -> String {
         format!("CHIM_TEST_KEY_{}", rand::random::<u32>())
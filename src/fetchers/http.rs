@@ -1,25 +1,99 @@
+use crate::checksum::Digester;
 use crate::config::Config;
+use crate::fetchers::{Fetched, Progress};
 use color_eyre::Result;
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest::Response;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
+use tokio::sync::mpsc;
 
-pub async fn fetch(config: &Config, url: &str, output: &Path) -> Result<()> {
-    let mut response = reqwest::get(url).await?;
-    response.error_for_status_ref()?;
+pub async fn fetch(
+    config: &Config,
+    url: &str,
+    output: &Path,
+    algorithm: Option<&str>,
+    progress: Option<&Progress>,
+) -> Result<Fetched> {
+    let (mut response, pb) = get(config, url, progress).await?;
     let mut file = File::create(output)?;
-    let pb = get_content_length(&response)
-        .map(|l| create_progress_bar(config, l))
-        .unwrap_or_else(ProgressBar::hidden);
+    let mut digester = algorithm.map(Digester::new).transpose()?;
 
     while let Some(chunk) = response.chunk().await? {
         pb.inc(chunk.len() as u64);
         file.write_all(&chunk)?;
+        if let Some(digester) = &mut digester {
+            digester.update(&chunk);
+        }
+    }
+
+    Ok(Fetched {
+        filename: get_content_disposition_filename(&response),
+        checksum: digester.map(Digester::finalize),
+    })
+}
+
+pub async fn stream(config: &Config, url: &str, tx: mpsc::Sender<Vec<u8>>) -> Result<Fetched> {
+    let (mut response, pb) = get(config, url, None).await?;
+
+    while let Some(chunk) = response.chunk().await? {
+        pb.inc(chunk.len() as u64);
+        if tx.send(chunk.to_vec()).await.is_err() {
+            // the receiver reports why it stopped reading
+            debug!("stream closed before download finished");
+            break;
+        }
+    }
+
+    Ok(Fetched {
+        filename: get_content_disposition_filename(&response),
+        ..Fetched::default()
+    })
+}
+
+async fn get(
+    config: &Config,
+    url: &str,
+    progress: Option<&Progress>,
+) -> Result<(Response, ProgressBar)> {
+    let response = reqwest::get(url).await?;
+    response.error_for_status_ref()?;
+    let pb = get_content_length(&response)
+        .map(|l| create_progress_bar(config, l, progress))
+        .unwrap_or_else(ProgressBar::hidden);
+
+    Ok((response, pb))
+}
+
+fn get_content_disposition_filename(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get("content-disposition")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_disposition_filename)
+}
+
+/// parses the filename out of a header like `attachment; filename="node.tar.gz"`
+/// the RFC 5987 `filename*=UTF-8''node.tar.gz` form is preferred if present
+fn parse_content_disposition_filename(header: &str) -> Option<String> {
+    let mut filename = None;
+    for param in header.split(';') {
+        match param.trim().split_once('=') {
+            Some((key, value)) if key.trim().eq_ignore_ascii_case("filename*") => {
+                return value
+                    .rsplit('\'')
+                    .next()
+                    .map(|v| v.trim_matches('"').to_string());
+            }
+            Some((key, value)) if key.trim().eq_ignore_ascii_case("filename") => {
+                filename = Some(value.trim().trim_matches('"').to_string());
+            }
+            _ => {}
+        }
     }
 
-    Ok(())
+    filename.filter(|f| !f.is_empty())
 }
 
 fn get_content_length(response: &Response) -> Option<u64> {
@@ -30,15 +104,41 @@ fn get_content_length(response: &Response) -> Option<u64> {
         .and_then(|v| v.parse::<u64>().ok())
 }
 
-fn create_progress_bar(config: &Config, length: u64) -> ProgressBar {
+fn create_progress_bar(config: &Config, length: u64, progress: Option<&Progress>) -> ProgressBar {
     if config.quiet {
         return ProgressBar::hidden();
     }
     let pb = ProgressBar::new(length);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+    pb.set_style(ProgressStyle::with_template("{spinner:.green} {prefix}[{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
         .unwrap()
         .progress_chars("=>-"));
+    if let Some(progress) = progress {
+        pb.set_prefix(format!("{:<16} ", progress.label));
+        return progress.bars.add(pb);
+    }
     //.with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
 
     pb
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    #[test_case("attachment; filename=\"node.tar.gz\"", Some("node.tar.gz"))]
+    #[test_case("attachment; filename=jq.zip", Some("jq.zip"))]
+    #[test_case(
+        "attachment; filename=\"a.bin\"; filename*=UTF-8''node.tar.xz",
+        Some("node.tar.xz")
+    )]
+    #[test_case("attachment; filename=\"\"", None)]
+    #[test_case("inline", None)]
+    fn test_parse_content_disposition_filename(header: &str, expected: Option<&str>) {
+        assert_eq!(
+            parse_content_disposition_filename(header),
+            expected.map(String::from)
+        );
+    }
+}
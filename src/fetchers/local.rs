@@ -0,0 +1,13 @@
+use crate::config::Config;
+use color_eyre::eyre::{Result, WrapErr};
+use std::fs;
+use std::path::Path;
+
+pub fn fetch(config: &Config, url: &str, output: &Path) -> Result<()> {
+    let filename = super::local_path(config, url);
+    debug!("copying {:?}", filename);
+    fs::copy(&filename, output)
+        .wrap_err_with(|| format!("error reading {}", filename.display()))?;
+
+    Ok(())
+}
@@ -1,31 +1,159 @@
 mod abs;
 mod gcs;
 mod http;
+mod local;
 mod s3;
 mod scp;
 
 use crate::config;
 use crate::config::Config;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use std::path::Path;
+use indicatif::MultiProgress;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// metadata about a fetched file
+#[derive(Debug, Default)]
+pub struct Fetched {
+    /// filename reported by the remote (e.g.: from a Content-Disposition header)
+    pub filename: Option<String>,
+    /// checksum like `sha256:abc123` computed while downloading
+    /// only set by fetchers that see the bytes, external CLI fetchers leave it to the caller
+    pub checksum: Option<String>,
+}
 
 pub struct Fetcher<'a> {
     config: &'a Config,
+    kind: config::Fetcher,
+    algorithm: Option<String>,
+    progress: Option<Progress>,
+}
+
+/// shows the download as one labeled bar among several concurrent downloads
+#[derive(Clone)]
+pub struct Progress {
+    pub bars: MultiProgress,
+    pub label: String,
+}
+
+pub fn new(config: &Config) -> Fetcher<'_> {
+    Fetcher {
+        config,
+        kind: config.fetcher,
+        algorithm: None,
+        progress: None,
+    }
+}
+
+/// a fetcher chosen by the protocol of `url` instead of the chim's main url
+pub fn for_url<'a>(config: &'a Config, url: &str) -> Result<Fetcher<'a>> {
+    Ok(Fetcher {
+        config,
+        kind: config::get_fetcher(url)?,
+        algorithm: None,
+        progress: None,
+    })
 }
 
-pub fn new(config: &Config) -> Fetcher {
-    Fetcher { config }
+/// the file a `local:` url points to, relative paths are relative to the chim's directory
+pub fn local_path(config: &Config, url: &str) -> PathBuf {
+    let path = url.strip_prefix("local:").unwrap_or(url);
+    config::chim_dir(&config.chim_path).join(path)
 }
 
 impl<'a> Fetcher<'a> {
-    pub async fn fetch(&self, url: &str, tmpfile: &Path) -> Result<()> {
-        match self.config.fetcher {
-            config::Fetcher::Http => http::fetch(self.config, url, tmpfile).await,
-            config::Fetcher::S3 => s3::fetch(self.config, url, tmpfile),
-            config::Fetcher::Gcs => gcs::fetch(url, tmpfile),
-            config::Fetcher::Abs => abs::fetch(url, tmpfile),
-            config::Fetcher::Scp => scp::fetch(url, tmpfile),
-            _ => panic!("unsupported fetcher"),
+    /// hash downloads with `algorithm` and return the checksum in `Fetched`
+    pub fn hash_with(mut self, algorithm: &str) -> Fetcher<'a> {
+        self.algorithm = Some(algorithm.to_string());
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Progress) -> Fetcher<'a> {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub async fn fetch(&self, url: &str, tmpfile: &Path) -> Result<Fetched> {
+        let algorithm = self.algorithm.as_deref();
+        let progress = self.progress.as_ref();
+        match self.kind {
+            config::Fetcher::Http => {
+                http::fetch(self.config, url, tmpfile, algorithm, progress).await
+            }
+            config::Fetcher::S3 => s3::fetch(self.config, url, tmpfile).map(|_| Fetched::default()),
+            config::Fetcher::Gcs => gcs::fetch(url, tmpfile).map(|_| Fetched::default()),
+            config::Fetcher::Abs => abs::fetch(url, tmpfile).map(|_| Fetched::default()),
+            config::Fetcher::Scp => scp::fetch(url, tmpfile).map(|_| Fetched::default()),
+            config::Fetcher::Local => {
+                local::fetch(self.config, url, tmpfile).map(|_| Fetched::default())
+            }
+        }
+    }
+
+    /// sends the download to `tx` in chunks instead of writing it to a file
+    pub async fn stream(&self, url: &str, tx: mpsc::Sender<Vec<u8>>) -> Result<Fetched> {
+        match self.kind {
+            config::Fetcher::Http => http::stream(self.config, url, tx).await,
+            kind => Err(eyre!(
+                "streaming is only supported for http, not {:?}",
+                kind
+            )),
         }
     }
 }
+
+/// reads the chunks sent by `Fetcher::stream`
+/// must be used from a blocking thread
+pub struct ChannelReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    pub fn new(rx: mpsc::Receiver<Vec<u8>>) -> ChannelReader {
+        ChannelReader {
+            rx,
+            chunk: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stream_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let chim_path = dir.path().join("tool");
+        std::fs::write(&chim_path, r#"url = "s3://tools/tool.tar.gz""#).unwrap();
+        let config = Config::from_chim_file(&chim_path, "linux", "x86_64", None).unwrap();
+        let (tx, _rx) = mpsc::channel(1);
+
+        let err = new(&config).stream(&config.url, tx).await.unwrap_err();
+        assert!(err.to_string().contains("only supported for http"));
+    }
+}
@@ -27,34 +27,19 @@ pub fn fetch(config: &Config, url: &str, output: &Path) -> Result<()> {
 }
 
 fn add_aws_args(config: &Config, cmd: &mut Command) {
-    match &config.aws_profile {
-        Some(aws_profile) => {
-            cmd.env("AWS_PROFILE", aws_profile);
-        }
-        None => {}
+    if let Some(aws_profile) = &config.aws_profile {
+        cmd.env("AWS_PROFILE", aws_profile);
     }
-    match &config.aws_access_key_id {
-        Some(aws_access_key_id) => {
-            cmd.env("AWS_ACCESS_KEY_ID", aws_access_key_id);
-        }
-        None => {}
+    if let Some(aws_access_key_id) = &config.aws_access_key_id {
+        cmd.env("AWS_ACCESS_KEY_ID", aws_access_key_id);
     }
-    match &config.aws_secret_access_key {
-        Some(aws_secret_access_key) => {
-            cmd.env("AWS_SECRET_ACCESS_KEY", aws_secret_access_key);
-        }
-        None => {}
+    if let Some(aws_secret_access_key) = &config.aws_secret_access_key {
+        cmd.env("AWS_SECRET_ACCESS_KEY", aws_secret_access_key);
     }
-    match &config.aws_access_token {
-        Some(aws_access_token) => {
-            cmd.env("AWS_ACCESS_TOKEN", aws_access_token);
-        }
-        None => {}
+    if let Some(aws_access_token) = &config.aws_access_token {
+        cmd.env("AWS_ACCESS_TOKEN", aws_access_token);
     }
-    match &config.aws_region {
-        Some(aws_region) => {
-            cmd.env("AWS_DEFAULT_REGION", aws_region);
-        }
-        None => {}
+    if let Some(aws_region) = &config.aws_region {
+        cmd.env("AWS_DEFAULT_REGION", aws_region);
     }
 }
@@ -1,4 +1,5 @@
 use crate::config::Config;
+use crate::schema;
 use color_eyre::eyre::{eyre, Context};
 use color_eyre::Result;
 use std::process::{Command, Stdio};
@@ -8,7 +9,7 @@ pub struct Hooks<'a> {
 }
 
 impl<'a> Hooks<'a> {
-    pub fn new(config: &Config) -> Hooks {
+    pub fn new(config: &Config) -> Hooks<'_> {
         Hooks { config }
     }
 
@@ -32,7 +33,14 @@ impl<'a> Hooks<'a> {
         match &script {
             Some(script) => {
                 debug!("running {hook} hook: {}", script);
-                let output = Command::new("sh")
+                let mut cmd = Command::new("sh");
+                if let Some(libc) = &self.config.libc {
+                    cmd.env("CHIM_LIBC", libc);
+                }
+                if schema::is_lenient() {
+                    cmd.env("CHIM_LENIENT", "1");
+                }
+                let output = cmd
                     .args(["-c", script])
                     .env("CHIM_URL", &self.config.url)
                     .env("CHIM_BIN_PATH", &self.config.bin_path)
@@ -64,7 +72,8 @@ mod tests {
 
     #[test]
     fn test_hooks() {
-        let config = Config::from_chim_file(Path::new("test/fixtures/hooks"), OS, ARCH).unwrap();
+        let config =
+            Config::from_chim_file(Path::new("test/fixtures/hooks"), OS, ARCH, None).unwrap();
 
         let hooks = Hooks::new(&config);
         assert_eq!(hooks.pre_fetch().unwrap(), "_pre_fetch_\n");
@@ -0,0 +1,102 @@
+use color_eyre::eyre::{Result, WrapErr};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const FILENAME: &str = "chim.lock";
+
+/// the entry for the top-level url, shared by platforms without their own
+pub const DEFAULT_PLATFORM: &str = "default";
+
+const HEADER: &str =
+    "# This file is generated by `chim checksums --lock`. Do not edit it by hand.\n";
+
+/// resolved urls and digests for every chim in a directory, written beside the chims
+/// e.g.: `[node.linux-x86_64]`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Lockfile {
+    chims: BTreeMap<String, BTreeMap<String, LockEntry>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockEntry {
+    pub url: String,
+    pub checksum: String,
+    pub size: Option<u64>,
+}
+
+impl Lockfile {
+    /// the lockfile that belongs to `chim_path`
+    pub fn path(chim_path: &Path) -> PathBuf {
+        chim_path.with_file_name(FILENAME)
+    }
+
+    pub fn load(filename: &Path) -> Result<Lockfile> {
+        if !filename.exists() {
+            return Ok(Lockfile::default());
+        }
+        trace!("reading {:?}", filename);
+        let body = fs::read_to_string(filename)?;
+        let lockfile = toml::from_str(&body)
+            .wrap_err_with(|| format!("error parsing {}", filename.display()))?;
+
+        Ok(lockfile)
+    }
+
+    pub fn save(&self, filename: &Path) -> Result<()> {
+        trace!("writing {:?}", filename);
+        fs::write(filename, format!("{HEADER}{}", toml::to_string(self)?))?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str, platform: &str) -> Option<&LockEntry> {
+        self.chims.get(name).and_then(|p| p.get(platform))
+    }
+
+    pub fn entries(&self, name: &str) -> BTreeMap<String, LockEntry> {
+        self.chims.get(name).cloned().unwrap_or_default()
+    }
+
+    /// replaces all of the entries of a chim
+    pub fn set(&mut self, name: &str, entries: BTreeMap<String, LockEntry>) {
+        self.chims.insert(name.to_string(), entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lockfile() {
+        let dir = tempdir().unwrap();
+        let filename = Lockfile::path(&dir.path().join("node"));
+        assert_eq!(filename, dir.path().join("chim.lock"));
+        assert!(Lockfile::load(&filename)
+            .unwrap()
+            .get("node", "linux-x86_64")
+            .is_none());
+
+        let entry = LockEntry {
+            url: "https://nodejs.org/dist/v18.7.0/node-v18.7.0-linux-x64.tar.xz".to_string(),
+            checksum: "sha256:8bc6a1b9deaed2586d726fc62d4bee9c1bfc5a30b96c1c4cff7edd15225a11a2"
+                .to_string(),
+            size: Some(23_000_000),
+        };
+        let mut lockfile = Lockfile::default();
+        lockfile.set("node", [("linux-x86_64".to_string(), entry.clone())].into());
+        lockfile.save(&filename).unwrap();
+
+        let body = fs::read_to_string(&filename).unwrap();
+        assert!(body.starts_with(HEADER));
+        assert!(body.contains("[node.linux-x86_64]"));
+        let lockfile = Lockfile::load(&filename).unwrap();
+        assert_eq!(lockfile.get("node", "linux-x86_64"), Some(&entry));
+        assert!(lockfile.get("node", "macos-aarch64").is_none());
+    }
+}
@@ -4,6 +4,7 @@ use color_eyre::Section;
 mod app;
 mod archive;
 mod bin;
+mod cache;
 mod checksum;
 mod chim_file;
 mod cli;
@@ -11,8 +12,13 @@ mod config;
 mod env;
 mod fetchers;
 mod hooks;
+mod lockfile;
 mod logger;
 mod platform;
+mod policy;
+mod schema;
+mod signature;
+mod template;
 
 #[macro_use]
 extern crate log;
@@ -1,26 +1,228 @@
-use std::borrow::Cow;
+use color_eyre::eyre::{eyre, Result};
+use color_eyre::Section;
+use std::env::consts::{ARCH, OS};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
 
-pub fn split_platform_name(name: &str) -> (Cow<'static, str>, Cow<'static, str>) {
-    let mut parts = name.split('-');
-    return (
-        normalize_os(parts.next().unwrap()),
-        normalize_arch(parts.next().unwrap()),
-    );
+/// values of rust's `std::env::consts::OS` that chims can target
+pub const OSES: [&str; 13] = [
+    "linux",
+    "macos",
+    "windows",
+    "freebsd",
+    "netbsd",
+    "openbsd",
+    "dragonfly",
+    "solaris",
+    "illumos",
+    "android",
+    "ios",
+    "aix",
+    "haiku",
+];
+
+/// values of rust's `std::env::consts::ARCH` that chims can target
+/// plus `armv7` and `powerpc64le` which rust reports as `arm` and `powerpc64`
+pub const ARCHES: [&str; 15] = [
+    "x86_64",
+    "x86",
+    "aarch64",
+    "armv7",
+    "arm",
+    "powerpc64le",
+    "powerpc64",
+    "powerpc",
+    "riscv64",
+    "s390x",
+    "mips",
+    "mips64",
+    "loongarch64",
+    "sparc64",
+    "wasm32",
+];
+
+/// the key of a platform table, None matches any os, arch or libc
+/// e.g.: `linux-x64`, `darwin-*`, `*-arm64`, `linux`, `linux-x64-musl` or `default`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PlatformKey {
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    /// gnu or musl
+    pub libc: Option<String>,
+}
+
+impl PlatformKey {
+    pub fn parse(name: &str) -> Result<PlatformKey> {
+        let name = name.to_lowercase();
+        let parts = name.split('-').collect::<Vec<_>>();
+        let (os, arch, libc) = match parts.as_slice() {
+            ["default"] | ["*"] => (None, None, None),
+            [os] => (Some(*os), None, None),
+            [os, libc] if normalize_libc(libc).is_some() => (Some(*os), None, Some(*libc)),
+            [os, arch] => (Some(*os), Some(*arch), None),
+            [os, arch, libc] => (Some(*os), Some(*arch), Some(*libc)),
+            _ => return Err(invalid(&name)),
+        };
+        if os.is_some_and(str::is_empty) || arch.is_some_and(str::is_empty) {
+            return Err(invalid(&name));
+        }
+        let libc = match libc {
+            Some(libc) => Some(normalize_libc(libc).ok_or_else(|| invalid(&name))?),
+            None => None,
+        };
+
+        Ok(PlatformKey {
+            os: os.and_then(normalize_os),
+            arch: arch.and_then(normalize_arch),
+            libc: libc.map(String::from),
+        })
+    }
+
+    /// tables for a libc never match if the host's libc is unknown
+    pub fn matches(&self, os: &str, arch: &str, libc: Option<&str>) -> bool {
+        self.os.as_ref().is_none_or(|o| o == os)
+            && self.arch.as_ref().is_none_or(|a| a == arch)
+            && self.libc.as_ref().is_none_or(|l| Some(l.as_str()) == libc)
+    }
+
+    /// tables with a higher specificity override those with a lower one
+    /// a libc only breaks ties so `linux-x64` still overrides `linux-musl`
+    pub fn specificity(&self) -> u8 {
+        let platform = match (&self.os, &self.arch) {
+            (Some(_), Some(_)) => 3,
+            (Some(_), None) => 2,
+            (None, Some(_)) => 1,
+            (None, None) => 0,
+        };
+        platform * 2 + u8::from(self.libc.is_some())
+    }
+
+    /// true if the key names a single os and arch
+    pub fn is_concrete(&self) -> bool {
+        self.os.is_some() && self.arch.is_some()
+    }
+}
+
+/// the host's libc on linux, CHIM_LIBC overrides the detected one
+pub fn host_libc() -> Result<Option<String>> {
+    match std::env::var("CHIM_LIBC") {
+        Ok(libc) => match normalize_libc(&libc) {
+            Some(libc) => Ok(Some(libc.to_string())),
+            None => Err(eyre!("invalid CHIM_LIBC: {libc}").suggestion("use gnu or musl")),
+        },
+        Err(_) if OS == "linux" => Ok(Some(detect_libc(Path::new("/")).to_string())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// musl systems have a dynamic loader like /lib/ld-musl-x86_64.so.1
+/// glibc systems can have the musl loader installed too so the glibc loader
+/// like /lib64/ld-linux-x86-64.so.2 wins if both are present
+fn detect_libc(root: &Path) -> &'static str {
+    let loaders = ["lib", "lib64", "lib32"]
+        .iter()
+        .filter_map(|dir| fs::read_dir(root.join(dir)).ok())
+        .flat_map(|entries| entries.flatten())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|f| f.starts_with("ld"))
+        .collect::<Vec<_>>();
+    let has_loader = |prefixes: &[&str]| {
+        loaders
+            .iter()
+            .any(|f| prefixes.iter().any(|p| f.starts_with(p)))
+    };
+    match has_loader(&["ld-linux", "ld64.so"]) {
+        true => "gnu",
+        false if has_loader(&["ld-musl-"]) => "musl",
+        false => "gnu",
+    }
+}
+
+impl Display for PlatformKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.os, &self.arch, &self.libc) {
+            (Some(os), Some(arch), None) => write!(f, "{os}-{arch}"),
+            (Some(os), None, None) => write!(f, "{os}"),
+            (None, Some(arch), None) => write!(f, "*-{arch}"),
+            (None, None, None) => write!(f, "default"),
+            (os, arch, Some(libc)) => write!(
+                f,
+                "{}-{}-{libc}",
+                os.as_deref().unwrap_or("*"),
+                arch.as_deref().unwrap_or("*")
+            ),
+        }
+    }
 }
 
-fn normalize_os(os: &str) -> Cow<'static, str> {
+fn invalid(name: &str) -> color_eyre::Report {
+    eyre!("invalid platform: {name}")
+        .suggestion("use os-arch like linux-x64, an os like linux, * wildcards or default")
+        .suggestion("add -gnu or -musl for a specific libc, e.g.: linux-x64-musl")
+}
+
+/// the platform chims are resolved for, CHIM_PLATFORM=os-arch overrides the host
+/// e.g.: to fetch linux-arm64 builds into a multi-arch docker image
+pub fn current() -> Result<(String, String, Option<String>)> {
+    let Ok(platform) = std::env::var("CHIM_PLATFORM") else {
+        return Ok((OS.to_string(), host_arch().to_string(), host_libc()?));
+    };
+    let key = PlatformKey::parse(&platform)?;
+    let (Some(os), Some(arch)) = (key.os, key.arch) else {
+        return Err(eyre!("invalid CHIM_PLATFORM: {platform}")
+            .suggestion("use os-arch like linux-arm64 or linux-x64-musl"));
+    };
+    let libc = match key.libc {
+        Some(libc) => Some(libc),
+        None if os == "linux" => host_libc()?,
+        None => None,
+    };
+    debug!("resolving chims for {os}-{arch} from CHIM_PLATFORM");
+
+    Ok((os, arch, libc))
+}
+
+/// rust's `std::env::consts::ARCH` with the ABIs it doesn't tell apart split out
+fn host_arch() -> &'static str {
+    match ARCH {
+        "arm" if cfg!(target_feature = "v7") => "armv7",
+        "powerpc64" if cfg!(target_endian = "little") => "powerpc64le",
+        arch => arch,
+    }
+}
+
+/// os names are normalized to rust's `std::env::consts::OS`
+fn normalize_os(os: &str) -> Option<String> {
     match os {
-        "darwin" => "macos".into(),
-        "win" => "windows".into(),
-        _ => os.to_owned().into(),
+        "*" => None,
+        "darwin" | "macosx" | "osx" | "mac" => Some("macos".into()),
+        "win" | "win32" | "win64" => Some("windows".into()),
+        _ => Some(os.into()),
+    }
+}
+
+fn normalize_libc(libc: &str) -> Option<&'static str> {
+    match libc {
+        "gnu" | "glibc" => Some("gnu"),
+        "musl" => Some("musl"),
+        _ => None,
     }
 }
 
-fn normalize_arch(arch: &str) -> Cow<'static, str> {
+/// arch names are normalized to rust's `std::env::consts::ARCH`
+fn normalize_arch(arch: &str) -> Option<String> {
     match arch {
-        "arm64" => "aarch64".into(),
-        "x64" => "x86_64".into(),
-        _ => arch.to_owned().into(),
+        "*" => None,
+        "arm64" | "armv8" => Some("aarch64".into()),
+        "x64" | "amd64" => Some("x86_64".into()),
+        "i386" | "i586" | "i686" | "386" | "ia32" => Some("x86".into()),
+        "armv7l" | "armhf" => Some("armv7".into()),
+        "armv6" | "armv6l" | "armel" => Some("arm".into()),
+        "ppc64le" | "ppc64el" => Some("powerpc64le".into()),
+        "ppc64" => Some("powerpc64".into()),
+        "riscv64gc" => Some("riscv64".into()),
+        _ => Some(arch.into()),
     }
 }
 
@@ -34,22 +236,98 @@ mod tests {
     #[test_case("macos-x64", "macos-x86_64")]
     #[test_case("macos-arm64", "macos-aarch64")]
     #[test_case("linux-aarch64", "linux-aarch64")]
-    fn test_split_platform_name(input: &str, expected: &str) {
-        let (os, arch) = split_platform_name(input);
-        assert_eq!(format!("{os}-{arch}"), expected);
+    #[test_case("Linux", "linux")]
+    #[test_case("linux-*", "linux" ; "any arch")]
+    #[test_case("*-arm64", "*-aarch64")]
+    #[test_case("*", "default")]
+    #[test_case("*-*", "default" ; "any os and arch")]
+    #[test_case("linux-x64-musl", "linux-x86_64-musl")]
+    #[test_case("linux-x64-glibc", "linux-x86_64-gnu")]
+    #[test_case("linux-musl", "linux-*-musl")]
+    fn test_parse(input: &str, expected: &str) {
+        assert_eq!(PlatformKey::parse(input).unwrap().to_string(), expected);
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("linux-" ; "empty arch")]
+    #[test_case("-x64" ; "empty os")]
+    #[test_case("linux-x64-extra" ; "unknown libc")]
+    #[test_case("linux-x64-musl-extra" ; "too many parts")]
+    fn test_parse_invalid(input: &str) {
+        assert!(PlatformKey::parse(input).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let key = PlatformKey::parse("linux").unwrap();
+        assert!(key.matches("linux", "x86_64", Some("gnu")));
+        assert!(key.matches("linux", "aarch64", None));
+        assert!(!key.matches("macos", "aarch64", None));
+        assert!(PlatformKey::parse("*-arm64")
+            .unwrap()
+            .matches("macos", "aarch64", None));
+        assert!(PlatformKey::parse("default")
+            .unwrap()
+            .matches("windows", "x86_64", None));
+
+        let key = PlatformKey::parse("linux-x64-musl").unwrap();
+        assert!(key.matches("linux", "x86_64", Some("musl")));
+        assert!(!key.matches("linux", "x86_64", Some("gnu")));
+        assert!(!key.matches("linux", "x86_64", None));
+        assert!(key.specificity() > PlatformKey::parse("linux-x64").unwrap().specificity());
     }
 
     #[test]
-    fn test_normalize_os() {
-        assert_eq!(normalize_os("darwin"), "macos");
-        assert_eq!(normalize_os("win"), "windows");
-        assert_eq!(normalize_os("linux"), "linux");
+    fn test_detect_libc() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        assert_eq!(detect_libc(root), "gnu");
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::create_dir_all(root.join("lib64")).unwrap();
+        fs::write(root.join("lib/ld-musl-x86_64.so.1"), "").unwrap();
+        assert_eq!(detect_libc(root), "musl");
+        // glibc with musl installed alongside it
+        fs::write(root.join("lib64/ld-linux-x86-64.so.2"), "").unwrap();
+        assert_eq!(detect_libc(root), "gnu");
+        fs::remove_file(root.join("lib64/ld-linux-x86-64.so.2")).unwrap();
+        fs::write(root.join("lib/ld-linux-aarch64.so.1"), "").unwrap();
+        assert_eq!(detect_libc(root), "gnu");
+    }
+
+    #[test_case("darwin", "macos")]
+    #[test_case("osx", "macos")]
+    #[test_case("win", "windows")]
+    #[test_case("win64", "windows")]
+    #[test_case("linux", "linux")]
+    #[test_case("freebsd", "freebsd")]
+    #[test_case("netbsd", "netbsd")]
+    fn test_normalize_os(os: &str, expected: &str) {
+        assert_eq!(normalize_os(os).unwrap(), expected);
+    }
+
+    #[test_case("arm64", "aarch64")]
+    #[test_case("x64", "x86_64")]
+    #[test_case("amd64", "x86_64")]
+    #[test_case("x86", "x86")]
+    #[test_case("i386", "x86")]
+    #[test_case("i686", "x86")]
+    #[test_case("armv7", "armv7")]
+    #[test_case("armv7l", "armv7")]
+    #[test_case("armhf", "armv7")]
+    #[test_case("armv6", "arm")]
+    #[test_case("armel", "arm")]
+    #[test_case("ppc64le", "powerpc64le")]
+    #[test_case("ppc64", "powerpc64")]
+    #[test_case("x32", "x32" ; "x32 is not x86")]
+    #[test_case("s390x", "s390x")]
+    #[test_case("riscv64", "riscv64")]
+    fn test_normalize_arch(arch: &str, expected: &str) {
+        assert_eq!(normalize_arch(arch).unwrap(), expected);
     }
 
     #[test]
-    fn test_normalize_arch() {
-        assert_eq!(normalize_arch("arm64"), "aarch64");
-        assert_eq!(normalize_arch("x64"), "x86_64");
-        assert_eq!(normalize_arch("x86"), "x86");
+    fn test_normalize_wildcard() {
+        assert_eq!(normalize_os("*"), None);
+        assert_eq!(normalize_arch("*"), None);
     }
 }
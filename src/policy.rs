@@ -0,0 +1,373 @@
+use crate::archive::Limits;
+use crate::config::{self, Config, Fetcher};
+use crate::env;
+use crate::fetchers;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use color_eyre::Section;
+use reqwest::Url;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// rules enforced before anything is fetched, extracted or executed
+/// paranoid mode (CHIM_PARANOID) starts from strict defaults,
+/// a policy file (CHIM_POLICY or ~/.config/chim/policy.toml) can override any rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Policy {
+    /// true if the strict paranoid defaults were used
+    pub paranoid: bool,
+    /// every artifact must have a checksum
+    pub require_checksum: bool,
+    /// md5 and sha1 checksums are rejected
+    pub forbid_weak_checksums: bool,
+    /// every artifact must have a signature_url and public_key
+    pub require_signature: bool,
+    /// plain http urls are rejected
+    pub https_only: bool,
+    /// if not empty, urls must be on one of these hosts, `*.example.com` matches subdomains
+    pub allowed_hosts: Vec<String>,
+    /// pre_fetch, pre_extract, pre_execute and post_execute hooks may run
+    pub allow_hooks: bool,
+    /// local: paths may point outside of the chim's directory
+    pub allow_local_paths_outside_chim_dir: bool,
+    /// modified installs are reinstalled instead of failing
+    pub reinstall_on_tamper: bool,
+    /// maximum total bytes unpacked from an archive
+    pub max_extracted_size: Option<u64>,
+    /// maximum number of entries unpacked from an archive
+    pub max_extracted_files: Option<u64>,
+}
+
+/// a policy file, unset rules keep their default
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    paranoid: Option<bool>,
+    require_checksum: Option<bool>,
+    forbid_weak_checksums: Option<bool>,
+    require_signature: Option<bool>,
+    https_only: Option<bool>,
+    allowed_hosts: Option<Vec<String>>,
+    allow_hooks: Option<bool>,
+    allow_local_paths_outside_chim_dir: Option<bool>,
+    reinstall_on_tamper: Option<bool>,
+    max_extracted_size: Option<u64>,
+    max_extracted_files: Option<u64>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            paranoid: false,
+            require_checksum: false,
+            forbid_weak_checksums: false,
+            require_signature: false,
+            https_only: false,
+            allowed_hosts: vec![],
+            allow_hooks: true,
+            allow_local_paths_outside_chim_dir: true,
+            reinstall_on_tamper: true,
+            max_extracted_size: None,
+            max_extracted_files: None,
+        }
+    }
+}
+
+impl Policy {
+    pub fn paranoid() -> Self {
+        Policy {
+            paranoid: true,
+            require_checksum: true,
+            forbid_weak_checksums: true,
+            require_signature: false,
+            https_only: true,
+            allowed_hosts: vec![],
+            allow_hooks: false,
+            allow_local_paths_outside_chim_dir: false,
+            reinstall_on_tamper: false,
+            max_extracted_size: Some(8 * 1024 * 1024 * 1024),
+            max_extracted_files: Some(100_000),
+        }
+    }
+
+    /// the effective policy from CHIM_PARANOID, CHIM_REQUIRE_SIGNATURE and the policy file
+    pub fn load() -> Result<Policy> {
+        let file = match path() {
+            Some(path) if path.exists() => read(&path)?,
+            _ => PolicyFile::default(),
+        };
+        let paranoid = env::var_is_true("CHIM_PARANOID") || file.paranoid == Some(true);
+        let mut policy = match paranoid {
+            true => Policy::paranoid(),
+            false => Policy::default(),
+        };
+        policy.merge(file);
+        if env::var_is_true("CHIM_REQUIRE_SIGNATURE") {
+            policy.require_signature = true;
+        }
+
+        Ok(policy)
+    }
+
+    fn merge(&mut self, file: PolicyFile) {
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(if let Some(v) = file.$field {
+                    self.$field = v;
+                })*
+            };
+        }
+        merge!(
+            require_checksum,
+            forbid_weak_checksums,
+            require_signature,
+            https_only,
+            allowed_hosts,
+            allow_hooks,
+            allow_local_paths_outside_chim_dir,
+            reinstall_on_tamper
+        );
+        if file.max_extracted_size.is_some() {
+            self.max_extracted_size = file.max_extracted_size;
+        }
+        if file.max_extracted_files.is_some() {
+            self.max_extracted_files = file.max_extracted_files;
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_size: self.max_extracted_size,
+            max_files: self.max_extracted_files,
+        }
+    }
+
+    /// checks the settings of a chim that can be verified before fetching
+    pub fn check(&self, config: &Config) -> Result<()> {
+        match config.fetcher {
+            Fetcher::Local => self.check_local_path(config, &config.bin_path)?,
+            _ => self.check_url(&config.url)?,
+        }
+        for url in [&config.checksum_url, &config.signature_url]
+            .into_iter()
+            .flatten()
+        {
+            self.check_fetch_url(config, url)?;
+        }
+        self.check_hooks(config)?;
+
+        Ok(())
+    }
+
+    pub fn check_url(&self, url: &str) -> Result<()> {
+        if !self.https_only && self.allowed_hosts.is_empty() {
+            return Ok(());
+        }
+        let parsed = Url::parse(url).wrap_err_with(|| format!("invalid url: {url}"))?;
+        if self.https_only && parsed.scheme() == "http" {
+            return Err(eyre!("{url} does not use https")
+                .suggestion("use an https url or set https_only = false in the policy"));
+        }
+        if self.allowed_hosts.is_empty() {
+            return Ok(());
+        }
+        let host = parsed.host_str().unwrap_or_default();
+        match self.allowed_hosts.iter().any(|h| host_matches(h, host)) {
+            true => Ok(()),
+            false => Err(eyre!("{host} is not an allowed host")
+                .section(format!("URL: {url}"))
+                .suggestion("add the host to allowed_hosts in the policy")),
+        }
+    }
+
+    fn check_hooks(&self, config: &Config) -> Result<()> {
+        if self.allow_hooks {
+            return Ok(());
+        }
+        let hooks = [
+            ("pre_fetch", &config.pre_fetch),
+            ("pre_extract", &config.pre_extract),
+            ("pre_execute", &config.pre_execute),
+            ("post_execute", &config.post_execute),
+        ];
+        match hooks.iter().find(|(_, hook)| hook.is_some()) {
+            Some((name, _)) => Err(
+                eyre!("{name} hooks are not allowed by the policy").suggestion(
+                    "remove the hook from the chim or set allow_hooks = true in the policy",
+                ),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// `local:` urls are files beside the chim, anything else is checked as a url
+    fn check_fetch_url(&self, config: &Config, url: &str) -> Result<()> {
+        match config::get_fetcher(url)? {
+            Fetcher::Local => self.check_local_path(config, &fetchers::local_path(config, url)),
+            _ => self.check_url(url),
+        }
+    }
+
+    fn check_local_path(&self, config: &Config, path: &Path) -> Result<()> {
+        if self.allow_local_paths_outside_chim_dir {
+            return Ok(());
+        }
+        let chim_dir = std::path::absolute(config::chim_dir(&config.chim_path))?;
+        let path = std::path::absolute(path)?;
+        if path.starts_with(&chim_dir) && !path.components().any(|c| c == Component::ParentDir) {
+            return Ok(());
+        }
+        Err(eyre!("{} is outside of the chim's directory", path.display())
+            .suggestion("use a path relative to the chim or set allow_local_paths_outside_chim_dir = true in the policy"))
+    }
+}
+
+/// `*.example.com` matches example.com and all of its subdomains
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let (pattern, host) = (pattern.to_lowercase(), host.to_lowercase());
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host == domain || host.ends_with(&format!(".{domain}")),
+        None => host == pattern,
+    }
+}
+
+/// CHIM_POLICY or ~/.config/chim/policy.toml
+pub fn path() -> Option<PathBuf> {
+    match std::env::var("CHIM_POLICY") {
+        Ok(path) => Some(PathBuf::from(path)),
+        Err(_) => dirs::config_dir().map(|d| d.join("chim").join("policy.toml")),
+    }
+}
+
+fn read(filename: &Path) -> Result<PolicyFile> {
+    trace!("reading {:?}", filename);
+    let body = fs::read_to_string(filename)?;
+    toml::from_str(&body).wrap_err_with(|| format!("error parsing {}", filename.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    #[test_case("example.com", "example.com", true)]
+    #[test_case("example.com", "EXAMPLE.com", true ; "case insensitive")]
+    #[test_case("example.com", "evil.com", false)]
+    #[test_case("*.example.com", "example.com", true ; "wildcard matches the domain itself")]
+    #[test_case("*.example.com", "dl.example.com", true)]
+    #[test_case("*.example.com", "evilexample.com", false)]
+    #[test_case("*.Example.com", "DL.example.COM", true ; "wildcard case insensitive")]
+    fn test_host_matches(pattern: &str, host: &str, expected: bool) {
+        assert_eq!(host_matches(pattern, host), expected);
+    }
+
+    #[test]
+    fn test_check_url() {
+        let policy = Policy {
+            https_only: true,
+            allowed_hosts: vec!["*.github.com".to_string()],
+            ..Policy::default()
+        };
+        policy
+            .check_url("https://objects.github.com/tool.tar.gz")
+            .unwrap();
+        assert!(policy
+            .check_url("http://objects.github.com/tool.tar.gz")
+            .is_err());
+        assert!(policy.check_url("https://example.com/tool.tar.gz").is_err());
+        Policy::default()
+            .check_url("http://example.com/tool.tar.gz")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check_hooks() {
+        let hooks =
+            Config::from_chim_file(Path::new("test/fixtures/hooks"), "linux", "x86_64", None)
+                .unwrap();
+        let env = Config::from_chim_file(Path::new("test/fixtures/env"), "linux", "x86_64", None)
+            .unwrap();
+        let err = Policy::paranoid().check_hooks(&hooks).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pre_fetch hooks are not allowed by the policy"
+        );
+        Policy::paranoid().check_hooks(&env).unwrap();
+        Policy::default().check_hooks(&hooks).unwrap();
+    }
+
+    #[test]
+    fn test_check_local_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let chim_path = dir.path().join("tools/tool");
+        fs::create_dir_all(chim_path.parent().unwrap()).unwrap();
+        let config = |body: &str| {
+            fs::write(&chim_path, body).unwrap();
+            Config::from_chim_file(&chim_path, "linux", "x86_64", None).unwrap()
+        };
+        let policy = Policy::paranoid();
+        policy.check(&config("path = 'bin/tool'")).unwrap();
+        assert!(policy.check(&config("path = '../bin/tool'")).is_err());
+        assert!(policy.check(&config("path = '/bin/sh'")).is_err());
+        Policy::default()
+            .check(&config("path = '/bin/sh'"))
+            .unwrap();
+
+        // local checksum and signature files are held to the same rule
+        let remote = "url = 'https://example.com/tool.tar.gz'\nchecksum = 'sha256:abc'";
+        policy
+            .check(&config(&format!(
+                "{remote}\nsignature_url = 'local:tool.sig'"
+            )))
+            .unwrap();
+        assert!(policy
+            .check(&config(&format!(
+                "{remote}\nsignature_url = 'local:../tool.sig'"
+            )))
+            .is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        assert_eq!(Policy::default().limits(), Limits::default());
+        let mut policy = Policy::default();
+        policy.merge(
+            toml::from_str(
+                r#"
+                max_extracted_size = 1024
+                max_extracted_files = 10
+                "#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            policy.limits(),
+            Limits {
+                max_size: Some(1024),
+                max_files: Some(10),
+            }
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut policy = Policy::paranoid();
+        policy.merge(
+            toml::from_str(
+                r#"
+                allow_hooks = true
+                allowed_hosts = ["nodejs.org"]
+                max_extracted_files = 10
+                "#,
+            )
+            .unwrap(),
+        );
+        assert!(policy.allow_hooks);
+        assert!(policy.https_only);
+        assert_eq!(policy.allowed_hosts, ["nodejs.org"]);
+        assert_eq!(policy.max_extracted_files, Some(10));
+
+        assert!(toml::from_str::<PolicyFile>("https_onyl = true").is_err());
+    }
+}
@@ -0,0 +1,573 @@
+use crate::chim_file::SETTING_TABLES;
+use crate::env;
+use crate::platform::{PlatformKey, ARCHES, OSES};
+use color_eyre::eyre::{eyre, Result};
+use color_eyre::Section;
+use serde::de::{self, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::fmt::Formatter;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use toml::{Spanned, Table};
+
+/// settings allowed in platform tables
+const PLATFORM_FIELDS: [&str; 26] = [
+    "version",
+    "ext",
+    "url",
+    "path",
+    "checksum",
+    "checksum_url",
+    "checksum_url_checksum",
+    "signature_url",
+    "public_key",
+    "bin_checksum",
+    "archive",
+    "inner",
+    "extract",
+    "strip_components",
+    "execvp",
+    "env",
+    "path_prepend",
+    "args",
+    "args_append",
+    "cwd",
+    "aws_profile",
+    "aws_access_key_id",
+    "aws_secret_access_key",
+    "aws_access_token",
+    "aws_region",
+    "fallback",
+];
+
+/// top-level settings that platform tables can't override
+const CHIM_FIELDS: [&str; 10] = [
+    "debug",
+    "quiet",
+    "extends",
+    "os_map",
+    "arch_map",
+    "pre_fetch",
+    "pre_extract",
+    "pre_execute",
+    "post_execute",
+    "pre_execute_interval",
+];
+
+/// set by `--lenient`, kept here rather than in CHIM_LENIENT as the runtime's threads may be reading the environment
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_lenient() {
+    LENIENT.store(true, Ordering::Relaxed);
+}
+
+/// passed on to executed programs and hooks as CHIM_LENIENT=1 so nested chims inherit it
+pub fn is_lenient() -> bool {
+    LENIENT.load(Ordering::Relaxed) || env::var_is_true("CHIM_LENIENT")
+}
+
+/// a key chim doesn't know and where it is in the chim
+#[derive(Debug, PartialEq, Eq)]
+struct Unknown {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+/// errors on keys that aren't settings or platforms so typos aren't silently ignored
+/// CHIM_LENIENT=1 or `--lenient` only warns about them, e.g.: for chims written for newer versions
+pub fn validate(body: &str, filename: &Path) -> Result<()> {
+    let unknown = find_unknown(body)?;
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    if is_lenient() {
+        for u in &unknown {
+            warn!(
+                "{}:{}:{}: {}",
+                filename.display(),
+                u.line,
+                u.column,
+                u.message
+            );
+        }
+        return Ok(());
+    }
+
+    Err(unknown
+        .iter()
+        .fold(eyre!("unknown keys in {}", filename.display()), |err, u| {
+            err.section(format!("{}:{}: {}", u.line, u.column, u.message))
+        }))
+    .suggestion("fix the keys or run with --lenient or CHIM_LENIENT=1 to ignore them")
+}
+
+fn find_unknown(body: &str) -> Result<Vec<Unknown>> {
+    let Keys(keys) = toml::from_str(body)?;
+    let mut unknown = vec![];
+    for (key, child) in keys {
+        let message = match &child {
+            Some(_) if SETTING_TABLES.contains(&key.get_ref().as_str()) => None,
+            Some(Keys(fields)) => {
+                unknown.extend(fields.iter().filter_map(|(field, value)| {
+                    check_platform_field(field.get_ref(), value.is_some())
+                        .map(|message| at(body, field, message))
+                }));
+                check_platform(key.get_ref())
+            }
+            None => check_field(key.get_ref()),
+        };
+        if let Some(message) = message {
+            unknown.push(at(body, &key, message));
+        }
+    }
+    unknown.sort_by_key(|u| (u.line, u.column));
+
+    Ok(unknown)
+}
+
+/// drops top-level values chim doesn't know, they would be parsed as platforms otherwise
+pub fn remove_unknown(table: &mut Table) {
+    let unknown = table
+        .iter()
+        .filter(|(key, value)| !value.is_table() && check_field(key).is_some())
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    for key in unknown {
+        table.remove(&key);
+    }
+}
+
+/// `fallback` only makes sense in a platform table
+fn check_field(key: &str) -> Option<String> {
+    match CHIM_FIELDS.contains(&key) || (PLATFORM_FIELDS.contains(&key) && key != "fallback") {
+        true => None,
+        false => Some(unknown_message(
+            "key",
+            key,
+            CHIM_FIELDS.iter().chain(&PLATFORM_FIELDS),
+        )),
+    }
+}
+
+/// `env` is the only table inside a platform
+fn check_platform_field(key: &str, is_table: bool) -> Option<String> {
+    match (PLATFORM_FIELDS.contains(&key), is_table == (key == "env")) {
+        (true, true) => None,
+        (true, false) if is_table => Some(format!("platform key `{key}` can't be a table")),
+        (true, false) => Some(format!("platform key `{key}` must be a table")),
+        (false, _) => Some(unknown_message("platform key", key, PLATFORM_FIELDS.iter())),
+    }
+}
+
+/// invalid platforms are left for the platform parser to report
+fn check_platform(name: &str) -> Option<String> {
+    let key = PlatformKey::parse(name).ok()?;
+    if let Some(os) = key.os.as_deref().filter(|os| !OSES.contains(os)) {
+        return Some(unknown_message(
+            &format!("os in [{name}], unknown"),
+            os,
+            OSES.iter(),
+        ));
+    }
+    if let Some(arch) = key.arch.as_deref().filter(|arch| !ARCHES.contains(arch)) {
+        return Some(unknown_message(
+            &format!("arch in [{name}], unknown"),
+            arch,
+            ARCHES.iter(),
+        ));
+    }
+
+    None
+}
+
+fn unknown_message<'a>(kind: &str, key: &str, known: impl Iterator<Item = &'a &'a str>) -> String {
+    match did_you_mean(key, known) {
+        Some(known) => format!("{kind} `{key}`, did you mean `{known}`?"),
+        None => format!("{kind} `{key}`"),
+    }
+}
+
+/// the closest known name if it's only a few edits away
+fn did_you_mean<'a>(key: &str, known: impl Iterator<Item = &'a &'a str>) -> Option<&'a str> {
+    known
+        .map(|k| (levenshtein(key, k), *k))
+        .filter(|(distance, _)| *distance <= key.len() / 3 + 1)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, k)| k)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = match ca == *cb {
+                true => prev,
+                false => 1 + prev.min(row[j]).min(cur),
+            };
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// the 1-based line and column of `key` in `body`
+fn at(body: &str, key: &Spanned<String>, message: String) -> Unknown {
+    let before = &body[..key.span().start];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    Unknown {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+        message,
+    }
+}
+
+/// the keys of a toml table with their spans, tables have the keys inside them
+struct Keys(Vec<(Spanned<String>, Option<Keys>)>);
+
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(KeysVisitor)
+    }
+}
+
+struct KeysVisitor;
+
+impl<'de> Visitor<'de> for KeysVisitor {
+    type Value = Keys;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a table")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Keys, A::Error> {
+        let mut keys = vec![];
+        while let Some(key) = map.next_key::<Spanned<String>>()? {
+            let Value(value) = map.next_value()?;
+            keys.push((key, value));
+        }
+
+        Ok(Keys(keys))
+    }
+}
+
+/// the keys of a table, None for any other value
+struct Value(Option<Keys>);
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a toml value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Value, A::Error> {
+        Ok(Value(Some(KeysVisitor.visit_map(map)?)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Value(None))
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Value, E> {
+        Ok(Value(None))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Value, E> {
+        Ok(Value(None))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Value, E> {
+        Ok(Value(None))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Value, E> {
+        Ok(Value(None))
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Value, E> {
+        Ok(Value(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    fn messages(body: &str) -> Vec<String> {
+        find_unknown(body)
+            .unwrap()
+            .into_iter()
+            .map(|u| format!("{}:{}: {}", u.line, u.column, u.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_unknown() {
+        assert_eq!(
+            messages(
+                r#"
+url = "https://example.com/tool-{os}.tar.gz"
+chekcsum = "sha256:abc"
+extends = "base"
+
+[os_map]
+macos = "darwin"
+
+[env]
+TOOL_HOME = "{install_dir}"
+
+[linux-x64]
+path = "bin/tool"
+  strip_compnents = 1
+frobnicate = true
+
+[linux-x64.env]
+TOOL_DEBUG = "1"
+
+[lnux-arm64]
+url = "https://example.com/tool.tar.gz"
+
+[macos-x86_46]
+url = "https://example.com/tool.tar.gz"
+
+[windows.args]
+"#
+            ),
+            vec![
+                "3:1: key `chekcsum`, did you mean `checksum`?",
+                "14:3: platform key `strip_compnents`, did you mean `strip_components`?",
+                "15:1: platform key `frobnicate`",
+                "20:2: os in [lnux-arm64], unknown `lnux`, did you mean `linux`?",
+                "23:2: arch in [macos-x86_46], unknown `x86_46`, did you mean `x86_64`?",
+                "26:10: platform key `args` can't be a table",
+            ]
+        );
+    }
+
+    #[test_case(r#"fallback = ["macos-x64"]"# ; "fallback is per platform")]
+    #[test_case("[quiet]" ; "setting as a table")]
+    fn test_find_unknown_invalid(body: &str) {
+        assert_eq!(find_unknown(body).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_remove_unknown() {
+        let mut table: Table = toml::from_str(
+            r#"
+            url = "https://example.com/tool.tar.gz"
+            chekcsum = "sha256:abc"
+            [linux-x64]
+            path = "bin/tool"
+            "#,
+        )
+        .unwrap();
+        remove_unknown(&mut table);
+        assert_eq!(table.keys().collect::<Vec<_>>(), vec!["linux-x64", "url"]);
+    }
+
+    #[test]
+    fn test_examples() {
+        for entry in std::fs::read_dir("example").unwrap() {
+            let path = entry.unwrap().path();
+            if !path.is_file() {
+                continue;
+            }
+            let body = std::fs::read_to_string(&path).unwrap();
+            if body.starts_with("#!/usr/bin/env chim") {
+                assert_eq!(messages(&body), Vec::<String>::new(), "{}", path.display());
+            }
+        }
+    }
+
+    /// the field names serde derived for a struct, passed to `deserialize_struct`
+    fn serde_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+        #[derive(Debug)]
+        struct Fields(&'static [&'static str]);
+
+        impl de::Error for Fields {
+            fn custom<M: std::fmt::Display>(_: M) -> Self {
+                Fields(&[])
+            }
+        }
+
+        impl std::error::Error for Fields {}
+
+        impl std::fmt::Display for Fields {
+            fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+                write!(f, "{:?}", self.0)
+            }
+        }
+
+        struct FieldsDeserializer;
+
+        impl<'de> Deserializer<'de> for FieldsDeserializer {
+            type Error = Fields;
+
+            fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Fields> {
+                Err(Fields(&[]))
+            }
+
+            fn deserialize_struct<V: Visitor<'de>>(
+                self,
+                _: &'static str,
+                fields: &'static [&'static str],
+                _: V,
+            ) -> Result<V::Value, Fields> {
+                Err(Fields(fields))
+            }
+
+            serde::forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+                bytes byte_buf option unit unit_struct newtype_struct seq tuple
+                tuple_struct map enum identifier ignored_any
+            }
+        }
+
+        match T::deserialize(FieldsDeserializer) {
+            Err(Fields(fields)) => fields,
+            Ok(_) => &[],
+        }
+    }
+
+    #[test]
+    fn test_platform_fields() {
+        let mut fields = serde_fields::<crate::chim_file::Platform>().to_vec();
+        fields.sort();
+        let mut expected = PLATFORM_FIELDS.to_vec();
+        expected.sort();
+        assert_eq!(fields, expected);
+    }
+
+    /// every setting chim accepts, keep it in sync with the fields of ChimFile and Platform
+    const ALL_FIELDS: &str = r#"
+debug = true
+quiet = true
+extends = "base.chim.toml"
+version = "1.0.0"
+ext = "tar.gz"
+url = "https://example.com/tool-{version}.{ext}"
+path = "bin/tool"
+checksum = "sha256:abc"
+checksum_url = "https://example.com/SHASUMS256.txt"
+checksum_url_checksum = "sha256:abc"
+signature_url = "https://example.com/tool.tar.gz.minisig"
+public_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"
+bin_checksum = "sha256:abc"
+archive = ["zip", "tar.gz"]
+inner = "tool.tar.gz"
+extract = ["bin/*"]
+strip_components = 1
+execvp = true
+path_prepend = ["{install_dir}/bin"]
+args = ["--flag"]
+args_append = ["--last"]
+cwd = "{chim_dir}"
+aws_profile = "tools"
+aws_access_key_id = "key"
+aws_secret_access_key = "secret"
+aws_access_token = "token"
+aws_region = "eu-west-1"
+pre_fetch = "true"
+pre_extract = "true"
+pre_execute = "true"
+post_execute = "true"
+pre_execute_interval = 3600
+
+[os_map]
+macos = "darwin"
+
+[arch_map]
+x86_64 = "x64"
+
+[env]
+TOOL_HOME = "{install_dir}"
+
+[linux-x86_64]
+version = "1.0.0"
+ext = "tar.gz"
+url = "https://example.com/tool-linux.tar.gz"
+path = "bin/tool"
+checksum = "sha256:abc"
+checksum_url = "https://example.com/SHASUMS256.txt"
+checksum_url_checksum = "sha256:abc"
+signature_url = "https://example.com/tool.tar.gz.minisig"
+public_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"
+bin_checksum = "sha256:abc"
+archive = "tar.gz"
+inner = "tool.tar.gz"
+extract = ["bin/*"]
+strip_components = 1
+execvp = true
+path_prepend = ["{install_dir}/bin"]
+args = ["--flag"]
+args_append = ["--last"]
+cwd = "{chim_dir}"
+aws_profile = "tools"
+aws_access_key_id = "key"
+aws_secret_access_key = "secret"
+aws_access_token = "token"
+aws_region = "eu-west-1"
+fallback = ["linux-x86"]
+
+[linux-x86_64.env]
+TOOL_HOME = "{install_dir}"
+"#;
+
+    #[test]
+    fn test_all_fields() {
+        assert_eq!(messages(ALL_FIELDS), Vec::<String>::new());
+
+        let mut table: Table = toml::from_str(ALL_FIELDS).unwrap();
+        let platform = table["linux-x86_64"].as_table().unwrap();
+        let mut keys = table
+            .keys()
+            .chain(platform.keys())
+            .filter(|key| *key != "linux-x86_64")
+            .map(String::as_str)
+            .unique()
+            .collect::<Vec<_>>();
+        keys.sort();
+        let mut expected = CHIM_FIELDS
+            .iter()
+            .chain(&PLATFORM_FIELDS)
+            .copied()
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        // extends is resolved before the chim is deserialized, any other field ChimFile
+        // doesn't have would be parsed as a platform
+        table.remove("extends");
+        let chim_file: crate::chim_file::ChimFile = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(
+            chim_file.platforms.keys().collect::<Vec<_>>(),
+            vec!["linux-x86_64"]
+        );
+    }
+
+    #[test_case("checksum", "checksum", 0)]
+    #[test_case("chekcsum", "checksum", 2)]
+    #[test_case("", "url", 3)]
+    fn test_levenshtein(a: &str, b: &str, expected: usize) {
+        assert_eq!(levenshtein(a, b), expected);
+    }
+}
@@ -0,0 +1,186 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use color_eyre::Section;
+use p256::ecdsa::signature::Verifier;
+use p256::pkcs8::DecodePublicKey;
+use pgp::types::KeyTrait;
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// the kind of key used to verify a signature, detected from the key itself
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyKind {
+    Minisign,
+    Cosign,
+    OpenPgp,
+}
+
+impl KeyKind {
+    pub fn detect(public_key: &str) -> KeyKind {
+        if public_key.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+            KeyKind::OpenPgp
+        } else if public_key.contains("-----BEGIN PUBLIC KEY-----") {
+            KeyKind::Cosign
+        } else {
+            KeyKind::Minisign
+        }
+    }
+}
+
+/// true if `public_key` is a key rather than a path to one
+/// single line values are only keys if they parse as a bare minisign key
+pub fn is_inline_key(public_key: &str) -> bool {
+    public_key.contains('\n')
+        || public_key.contains("-----BEGIN")
+        || minisign_verify::PublicKey::from_base64(public_key.trim()).is_ok()
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyKind::Minisign => write!(f, "minisign"),
+            KeyKind::Cosign => write!(f, "cosign"),
+            KeyKind::OpenPgp => write!(f, "OpenPGP"),
+        }
+    }
+}
+
+/// verifies a detached `signature` of `filename` against a trusted `public_key`
+/// works offline, the signature and key must already be available
+pub fn verify(filename: &Path, signature: &[u8], public_key: &str) -> Result<()> {
+    let data = fs::read(filename)?;
+    let kind = KeyKind::detect(public_key);
+    debug!("verifying {} signature of {:?}", kind, filename);
+    match kind {
+        KeyKind::Minisign => verify_minisign(&data, signature, public_key),
+        KeyKind::Cosign => verify_cosign(&data, signature, public_key),
+        KeyKind::OpenPgp => verify_openpgp(&data, signature, public_key),
+    }
+    .wrap_err_with(|| format!("invalid {} signature for {}", kind, filename.display()))
+}
+
+fn verify_minisign(data: &[u8], signature: &[u8], public_key: &str) -> Result<()> {
+    let public_key = public_key.trim();
+    let public_key = match public_key.lines().count() {
+        1 => minisign_verify::PublicKey::from_base64(public_key),
+        _ => minisign_verify::PublicKey::decode(public_key),
+    }
+    .map_err(|e| eyre!("invalid minisign public key: {e}"))?;
+    let signature = minisign_verify::Signature::decode(&String::from_utf8_lossy(signature))
+        .map_err(|e| eyre!("invalid minisign signature: {e}"))?;
+    public_key
+        .verify(data, &signature, false)
+        .map_err(|e| eyre!("{e}"))?;
+    debug!("trusted comment: {}", signature.trusted_comment());
+
+    Ok(())
+}
+
+fn verify_cosign(data: &[u8], signature: &[u8], public_key: &str) -> Result<()> {
+    let key = p256::ecdsa::VerifyingKey::from_public_key_pem(public_key.trim())
+        .map_err(|e| eyre!("invalid cosign public key: {e}"))
+        .suggestion("only ECDSA P-256 cosign keys are supported")?;
+    let signature = String::from_utf8_lossy(signature);
+    let signature = match signature.trim() {
+        s if s.starts_with('{') => parse_sigstore_bundle(s)?,
+        s => s.to_string(),
+    };
+    let signature = BASE64
+        .decode(signature)
+        .wrap_err("invalid base64 in cosign signature")?;
+    let signature = p256::ecdsa::DerSignature::from_bytes(&signature)
+        .map_err(|e| eyre!("invalid cosign signature: {e}"))?;
+    key.verify(data, &signature)
+        .map_err(|_| eyre!("signature does not match"))?;
+
+    Ok(())
+}
+
+/// extracts the base64 signature from a sigstore bundle
+/// or from a bundle written by `cosign sign-blob --bundle`
+fn parse_sigstore_bundle(bundle: &str) -> Result<String> {
+    let bundle: Value = serde_json::from_str(bundle).wrap_err("invalid sigstore bundle")?;
+    let signature = bundle
+        .pointer("/messageSignature/signature")
+        .or_else(|| bundle.get("base64Signature"))
+        .and_then(|s| s.as_str())
+        .ok_or_else(|| eyre!("no message signature found in sigstore bundle"))?;
+
+    Ok(signature.to_string())
+}
+
+fn verify_openpgp(data: &[u8], signature: &[u8], public_key: &str) -> Result<()> {
+    let (key, _) = SignedPublicKey::from_string(public_key)
+        .map_err(|e| eyre!("invalid OpenPGP public key: {e}"))?;
+    key.verify()
+        .map_err(|e| eyre!("invalid OpenPGP public key: {e}"))?;
+    let signature = match std::str::from_utf8(signature) {
+        Ok(s) if s.contains("-----BEGIN PGP SIGNATURE-----") => {
+            StandaloneSignature::from_string(s).map(|(s, _)| s)
+        }
+        _ => StandaloneSignature::from_bytes(signature),
+    }
+    .map_err(|e| eyre!("invalid OpenPGP signature: {e}"))?;
+
+    if signature.verify(&key.primary_key, data).is_ok() {
+        return Ok(());
+    }
+    for subkey in &key.public_subkeys {
+        if signature.verify(&subkey.key, data).is_ok() {
+            debug!("signed by subkey {}", hex::encode(subkey.key.key_id()));
+            return Ok(());
+        }
+    }
+
+    Err(eyre!("signature does not match"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    const FIXTURES: &str = "test/fixtures/signature";
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(Path::new(FIXTURES).join(name)).unwrap()
+    }
+
+    #[test_case("minisign.pub", "artifact.minisig", KeyKind::Minisign)]
+    #[test_case("cosign.pub", "artifact.sig", KeyKind::Cosign)]
+    #[test_case("cosign.pub", "artifact.sigstore.json", KeyKind::Cosign)]
+    #[test_case("pgp.asc", "artifact.asc", KeyKind::OpenPgp)]
+    fn test_verify(key: &str, signature: &str, kind: KeyKind) {
+        let key = String::from_utf8(fixture(key)).unwrap();
+        let signature = fixture(signature);
+        assert_eq!(KeyKind::detect(&key), kind);
+
+        let artifact = Path::new(FIXTURES).join("artifact");
+        verify(&artifact, &signature, &key).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let tampered = dir.path().join("artifact");
+        fs::write(&tampered, "goodbye chim\n").unwrap();
+        assert!(verify(&tampered, &signature, &key).is_err());
+    }
+
+    #[test]
+    fn test_verify_minisign_bare_key() {
+        let key = String::from_utf8(fixture("minisign.pub")).unwrap();
+        let key = key.lines().last().unwrap();
+        let artifact = Path::new(FIXTURES).join("artifact");
+        verify(&artifact, &fixture("artifact.minisig"), key).unwrap();
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let key = String::from_utf8(fixture("cosign.pub")).unwrap();
+        let artifact = Path::new(FIXTURES).join("artifact");
+        assert!(verify(&artifact, &fixture("artifact.minisig"), &key).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::chim_file::{ChimFile, Platform};
+use color_eyre::eyre::{eyre, Result};
+use color_eyre::Section;
+
+/// placeholders that differ between platforms
+const PLATFORM_PLACEHOLDERS: [&str; 4] = ["{os}", "{arch}", "{libc}", "{ext}"];
+
+/// values substituted for `{version}`, `{os}`, `{arch}`, `{libc}` and `{ext}` in a chim
+/// os and arch are chim's normalized names translated with `os_map` and `arch_map`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vars {
+    pub version: Option<String>,
+    pub os: String,
+    pub arch: String,
+    /// gnu or musl on linux
+    pub libc: Option<String>,
+    pub ext: Option<String>,
+}
+
+impl Vars {
+    pub fn new(
+        chim_file: &ChimFile,
+        platform: &Platform,
+        os: &str,
+        arch: &str,
+        libc: Option<&str>,
+    ) -> Vars {
+        Vars {
+            version: platform
+                .version
+                .clone()
+                .or_else(|| chim_file.version.clone()),
+            os: chim_file
+                .os_map
+                .get(os)
+                .cloned()
+                .unwrap_or_else(|| os.to_string()),
+            arch: chim_file
+                .arch_map
+                .get(arch)
+                .cloned()
+                .unwrap_or_else(|| arch.to_string()),
+            libc: libc.map(String::from),
+            ext: platform.ext.clone().or_else(|| chim_file.ext.clone()),
+        }
+    }
+
+    /// replaces the known placeholders in `s`, any other braces are left alone
+    pub fn render(&self, s: &str) -> Result<String> {
+        let vars = [
+            ("version", self.version.as_deref()),
+            ("os", Some(self.os.as_str())),
+            ("arch", Some(self.arch.as_str())),
+            ("libc", self.libc.as_deref()),
+            ("ext", self.ext.as_deref()),
+        ];
+        let mut out = s.to_string();
+        for (name, value) in vars {
+            let placeholder = format!("{{{name}}}");
+            if !out.contains(&placeholder) {
+                continue;
+            }
+            let value = value.ok_or_else(|| {
+                eyre!("{placeholder} is used but {name} is not set")
+                    .section(format!("Template: {s}"))
+                    .suggestion(format!("add `{name} = \"...\"` to the chim or platform"))
+            })?;
+            out = out.replace(&placeholder, value);
+        }
+
+        Ok(out)
+    }
+
+    pub fn render_opt(&self, s: Option<String>) -> Result<Option<String>> {
+        s.map(|s| self.render(&s)).transpose()
+    }
+}
+
+/// true if `s` renders differently on each platform
+pub fn is_platform_specific(s: &str) -> bool {
+    PLATFORM_PLACEHOLDERS.iter().any(|p| s.contains(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_render() {
+        let chim_file = ChimFile {
+            version: Some("18.7.0".to_string()),
+            ext: Some("tar.gz".to_string()),
+            os_map: [("macos".to_string(), "darwin".to_string())].into(),
+            arch_map: [("aarch64".to_string(), "arm64".to_string())].into(),
+            ..ChimFile::default()
+        };
+        let vars = Vars::new(&chim_file, &Platform::default(), "macos", "aarch64", None);
+        assert_eq!(
+            vars.render("https://nodejs.org/dist/v{version}/node-v{version}-{os}-{arch}.{ext}")
+                .unwrap(),
+            "https://nodejs.org/dist/v18.7.0/node-v18.7.0-darwin-arm64.tar.gz"
+        );
+        assert_eq!(
+            vars.render("echo ${HOME} {foo}").unwrap(),
+            "echo ${HOME} {foo}"
+        );
+
+        let platform = Platform {
+            ext: Some("zip".to_string()),
+            ..Platform::default()
+        };
+        let vars = Vars::new(&chim_file, &platform, "windows", "x86_64", None);
+        assert_eq!(
+            vars.render("node-{os}-{arch}.{ext}").unwrap(),
+            "node-windows-x86_64.zip"
+        );
+
+        let vars = Vars::new(
+            &ChimFile::default(),
+            &Platform::default(),
+            "linux",
+            "x86_64",
+            Some("musl"),
+        );
+        assert!(vars.render("node-v{version}").is_err());
+        assert_eq!(
+            vars.render("tool-{arch}-{libc}").unwrap(),
+            "tool-x86_64-musl"
+        );
+    }
+
+    #[test]
+    fn test_is_platform_specific() {
+        assert!(is_platform_specific(
+            "https://example.com/{version}/tool-{os}"
+        ));
+        assert!(!is_platform_specific("https://example.com/{version}/tool"));
+    }
+}